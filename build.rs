@@ -1,4 +1,4 @@
 pub fn main() {
-    #[cfg(target-os = "windows")]
+    #[cfg(target_os = "windows")]
     println!("cargo:rustc-link-search=target/sdl");
 }
@@ -0,0 +1,96 @@
+# Deutsche Texte des Epoch Wars Clients.
+
+## Lobby und Kopfzeile
+
+waiting-for-server = Warte auf Server ...
+turn = Runde { $turn }
+turn-spectating = Runde { $turn } (Zuschauer)
+turn-planning = Runde { $turn } (Planung)
+reconnecting = Verbinde neu ...
+time-left = Zeit { $time }
+
+## Gebäude
+
+building-house = Haus
+building-villa = Villa
+building-tower = Turm
+building-unknown = Unbekanntes Gebäude ({ $kind })
+
+## Tooltips
+
+cell = Feld { $x }, { $y }
+cell-building = Gebäude: { $building }
+cell-excavated = Ausgegraben: { $building } in Tiefe { $depth }
+cell-excavated-nothing = Ausgegraben: nichts gefunden
+connection-good = Verbindung: gut
+connection-fair = Verbindung: mittel
+connection-poor = Verbindung: schlecht
+connection-lost = Verbindung verloren, verbinde neu
+connection-unknown = Verbindung: nicht gemessen
+latency = Latenz: { $ms } ms
+price = Preis: { $price }
+towers = Türme: { $count }
+already-built = In dieser Runde schon gebaut
+end-turn = Runde beenden
+end-turn-planned = Sendet die geplanten Aktionen
+shortcut = Taste: { $key }
+shortcut-space = Leertaste
+excavate = Ausgraben
+excavate-hint = Rechtsklick auf ein Feld
+already-excavated = In dieser Runde schon ausgegraben
+
+## Ausgrabungsergebnisse
+
+excavation-results = Ausgrabungsergebnisse
+excavation-found = { $building } in Tiefe { $depth } an Position { $x }, { $y } gefunden.
+excavation-nothing = Nichts gefunden an Position { $x }, { $y }.
+
+## Einstellungen
+
+settings = Einstellungen
+volume-master = Gesamtlautstärke
+volume-music = Musiklautstärke
+volume-effects = Effektlautstärke
+volume-muted = (stumm)
+palette = Farben: { $palette }
+palette-theme = Farben des Themas
+palette-red-green = Für Rot-Grün-Schwäche
+palette-blue-yellow = Für Blau-Gelb-Schwäche
+palette-monochrome = Einfarbig
+shape-cues-on = Formen als Hinweise: an
+shape-cues-off = Formen als Hinweise: aus
+settings-help = Hoch/Runter: auswählen, Links/Rechts: ändern, M: stumm, O: schließen
+
+## Nachrichten des Servers
+
+game-over = Spielende
+final-score = Punkte: { $score }
+error = Fehler
+warning = Warnung
+incompatible-server = Inkompatibler Server
+incompatible-protocol =
+    Der Server nutzt Protokoll { $version }, aber dieser Client unterstützt Version { $supported }.
+protocol-version = Version { $version }
+protocol-unknown = eine unbekannte Version
+use-older-client = Bitte nutze einen älteren Client oder aktualisiere den Server.
+upgrade-client = Bitte aktualisiere den Client.
+unknown-data =
+    Der Server hat { $data } gesendet, die dieser Client nicht versteht. Der Server ist eventuell neuer als dieser Client.
+unknown-buildings = Gebäude vom Typ { $kinds }
+unknown-messages = Nachrichten vom Typ { $kind }
+malformed-messages = fehlerhafte Nachrichten
+
+## Verbindung
+
+connecting = Verbinde mit { $address } ...
+connected = Verbunden mit { $address }.
+connection-failed = Verbindung zu { $address } fehlgeschlagen: { $error }
+connection-lost-title = Verbindung verloren
+reconnect-failed = Neue Verbindung zum Server nicht möglich: { $error }
+reconnect-attempt = Verbindung verloren, verbinde neu ({ $attempt }/{ $attempts }) ...
+error-resolve = Die Serveradresse wurde nicht gefunden.
+error-refused = Der Server hat die Verbindung abgelehnt.
+error-timeout = Der Server hat nicht rechtzeitig geantwortet.
+error-closed = Der Server hat die Verbindung geschlossen.
+error-tls = Es konnte keine sichere Verbindung zum Server hergestellt werden.
+error-connection = Die Verbindung zum Server ist fehlgeschlagen.
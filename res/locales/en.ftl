@@ -0,0 +1,97 @@
+# Messages of the Epoch Wars client. Translations go into locales/<language>.ftl
+# of the resource directories, e.g. locales/de.ftl.
+
+## Lobby and header
+
+waiting-for-server = Waiting for server ...
+turn = Turn { $turn }
+turn-spectating = Turn { $turn } (spectating)
+turn-planning = Turn { $turn } (planning)
+reconnecting = Reconnecting ...
+time-left = Time { $time }
+
+## Buildings
+
+building-house = House
+building-villa = Villa
+building-tower = Tower
+building-unknown = Unknown building ({ $kind })
+
+## Tooltips
+
+cell = Cell { $x }, { $y }
+cell-building = Building: { $building }
+cell-excavated = Excavated: { $building } at depth { $depth }
+cell-excavated-nothing = Excavated: nothing found
+connection-good = Connection: good
+connection-fair = Connection: fair
+connection-poor = Connection: poor
+connection-lost = Connection lost, reconnecting
+connection-unknown = Connection: not measured
+latency = Latency: { $ms } ms
+price = Price: { $price }
+towers = Towers: { $count }
+already-built = Already built this turn
+end-turn = End turn
+end-turn-planned = Sends planned actions
+shortcut = Shortcut: { $key }
+shortcut-space = Space
+excavate = Excavate
+excavate-hint = Right click a cell
+already-excavated = Already excavated this turn
+
+## Excavation results
+
+excavation-results = Excavation Results
+excavation-found = Found { $building } at depth { $depth } on position { $x }, { $y }.
+excavation-nothing = Found nothing at position { $x }, { $y }.
+
+## Settings
+
+settings = Settings
+volume-master = Master volume
+volume-music = Music volume
+volume-effects = Effects volume
+volume-muted = (muted)
+palette = Colours: { $palette }
+palette-theme = Theme colours
+palette-red-green = Red-green safe
+palette-blue-yellow = Blue-yellow safe
+palette-monochrome = Monochrome
+shape-cues-on = Shape cues: on
+shape-cues-off = Shape cues: off
+settings-help = Up/Down: select, Left/Right: change, M: mute, O: close
+
+## Messages from the server
+
+game-over = Finish
+final-score = Score: { $score }
+error = Error
+warning = Warning
+incompatible-server = Incompatible server
+incompatible-protocol =
+    The server uses protocol { $version }, but this client supports version { $supported }.
+protocol-version = version { $version }
+protocol-unknown = an unknown version
+use-older-client = Please use an older client or update the server.
+upgrade-client = Please upgrade the client.
+unknown-data =
+    The server sent { $data } that this client does not understand. The server may be newer than this client.
+unknown-buildings = buildings of type { $kinds }
+unknown-messages = messages of type { $kind }
+malformed-messages = malformed messages
+
+## Connection
+
+connecting = Connecting to { $address } ...
+connected = Connected to { $address }.
+connection-failed = Connection to { $address } failed: { $error }
+connection-lost-title = Connection lost
+reconnect-failed = Unable to reconnect to the server: { $error }
+reconnect-attempt = Connection lost, reconnecting ({ $attempt }/{ $attempts }) ...
+error-resolve = The server address could not be found.
+error-refused = The server refused the connection.
+error-timeout = The server did not answer in time.
+error-closed = The server closed the connection.
+error-tls = No secure connection to the server could be established.
+error-connection = The connection to the server failed.
@@ -1,78 +1,296 @@
 use failure::Error;
-use rodio::{default_output_device, Decoder, Sink, Source};
-use std::fs::File;
-use std::io::BufReader;
-use std::sync::mpsc::Receiver;
-use std::thread;
+use rodio::dynamic_mixer::{self, DynamicMixerController};
+use rodio::buffer::SamplesBuffer;
+use rodio::source::{Buffered, Zero};
+use rodio::{default_output_device, Decoder, Device, Sink, Source};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::io::Cursor;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
 
-use super::message::AudioMessage;
+use super::config::Volumes;
+use super::message::{AudioMessage, Channel, Effect, Phase};
+use super::music::{Playlist, Track};
+use super::resources::{Resource, Resources};
 
 pub struct Audio {
-    bg_music: String,
-    build: String,
+    resources: Resources,
+    volumes: Volumes,
+    /// Whether to play sounds at all, otherwise the null backend is used.
+    enabled: bool,
     rx: Receiver<AudioMessage>,
+    /// Opens the audio device.
+    open: fn() -> Result<Box<dyn Backend>, Error>,
+}
+
+const CHANNELS: u16 = 2;
+const SAMPLE_RATE: u32 = 44100;
+
+/// Volume of the generated default sounds.
+const TONE_VOLUME: f32 = 0.25;
+/// Samples faded in and out at each note of a generated sound.
+const TONE_FADE: usize = 200;
+/// Milliseconds between checks whether the next music track has to start.
+const PLAYLIST_INTERVAL: u64 = 100;
+
+type Sound = Buffered<SamplesBuffer<f32>>;
+
+/// Plays music and sound effects.
+trait Backend {
+    fn play_music(&mut self, track: Track);
+    fn play_effect(&mut self, sound: &Sound);
+    fn set_volumes(&mut self, volumes: &Volumes);
+    fn stop(&mut self);
+}
+
+/// The audio output, shared by a music and a sound effect channel.
+struct Mixer {
+    music: Sink,
+    effects: Sink,
+    music_input: Arc<DynamicMixerController<f32>>,
+    effects_input: Arc<DynamicMixerController<f32>>,
 }
 
 impl Audio {
-    pub fn new(rx: Receiver<AudioMessage>) -> Result<Audio, Error> {
-        let base_res_path = {
-            let mut exe = ::std::env::current_exe().unwrap();
-            exe.pop();
-            if cfg!(target_os = "macos") {
-                exe.to_str().unwrap().to_string() + "/../Resources/"
-            } else if cfg!(debug_assertions) {
-                String::new()
-            } else {
-                exe.to_str().unwrap().to_string() + "/"
-            }
-        };
+    pub fn new(
+        rx: Receiver<AudioMessage>,
+        volumes: Volumes,
+        enabled: bool,
+        resources: Resources,
+    ) -> Result<Audio, Error> {
         Ok(Audio {
-            bg_music: base_res_path.clone() + "res/bg.ogg",
-            build: base_res_path + "res/build.ogg",
+            resources,
+            volumes,
+            enabled,
             rx,
+            open: Mixer::open,
         })
     }
 
-    pub fn run(&self) {
-        if let Err(err) = self.run_res() {
-            for e in err.iter_chain() {
-                error!("{}", e);
+    /// Plays sounds until `AudioMessage::Quit` is received. Returns whether
+    /// the audio thread finished without an error.
+    pub fn run(&self) -> bool {
+        match self.run_res() {
+            Ok(()) => true,
+            Err(err) => {
+                for e in err.iter_chain() {
+                    error!("{}", e);
+                }
+                false
             }
         }
     }
 
     pub fn run_res(&self) -> Result<(), Error> {
-        let bg_music = self.bg_music.clone();
-        thread::spawn(move || {
-            let device = default_output_device()
-                .ok_or(format_err!("Unable to open audio device."))
-                .unwrap();
-            let sink = Sink::new(&device);
-            debug!("Playing {}", bg_music);
-            let bg_file = File::open(&bg_music).unwrap();
-            let source = Decoder::new(BufReader::new(bg_file)).unwrap();
-            sink.append(source.buffered().repeat_infinite());
-            sink.sleep_until_end();
-        });
-
-        while let Ok(msg) = self.rx.recv() {
+        let mut volumes = self.volumes;
+        let mut mixer = self.backend();
+        mixer.set_volumes(&volumes);
+        let mut playlist = Playlist::new(self.resources.clone());
+        if let Some(track) = playlist.set_phase(Phase::Lobby) {
+            mixer.play_music(track);
+        }
+        let effects: HashMap<Effect, Sound> =
+            Effect::ALL.iter().map(|&e| (e, self.load(e))).collect();
+        loop {
+            let msg = match self.rx.recv_timeout(Duration::from_millis(PLAYLIST_INTERVAL)) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(track) = playlist.update() {
+                        mixer.play_music(track);
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
             match msg {
-                AudioMessage::Build => {
-                    let build = self.build.clone();
-                    thread::spawn(move || {
-                        let device = default_output_device()
-                            .ok_or(format_err!("Unable to open audio device."))
-                            .unwrap();
-                        let sink = Sink::new(&device);
-                        debug!("Playing {}", build);
-                        let file = File::open(&build).unwrap();
-                        let source = Decoder::new(BufReader::new(file)).unwrap();
-                        sink.append(source.buffered());
-                        sink.sleep_until_end();
-                    });
+                AudioMessage::Play(effect) => mixer.play_effect(&effects[&effect]),
+                AudioMessage::Phase(phase) => {
+                    if let Some(track) = playlist.set_phase(phase) {
+                        mixer.play_music(track);
+                    }
+                }
+                AudioMessage::Volume(channel, level) => {
+                    volumes.get_mut(channel).level = level;
+                    mixer.set_volumes(&volumes);
                 }
+                AudioMessage::Mute(channel, muted) => {
+                    volumes.get_mut(channel).muted = muted;
+                    mixer.set_volumes(&volumes);
+                }
+                AudioMessage::Quit => break,
             }
         }
+        mixer.stop();
+        debug!("Audio thread finished.");
         Ok(())
     }
+
+    /// Opens the audio device, falling back to the null backend if audio is
+    /// disabled or no device is available.
+    fn backend(&self) -> Box<dyn Backend> {
+        if !self.enabled {
+            info!("Audio is disabled.");
+            return Box::new(NullBackend);
+        }
+        match (self.open)() {
+            Ok(mixer) => mixer,
+            Err(e) => {
+                warn!("{} Continuing without audio.", e);
+                Box::new(NullBackend)
+            }
+        }
+    }
+
+    /// Loads the sound of an effect from `sounds/<name>.ogg`, falling back to
+    /// the default sound.
+    fn load(&self, effect: Effect) -> Sound {
+        let mut resources = vec![self.resources.find(&format!("sounds/{}.ogg", effect.name()))];
+        if effect == Effect::Build {
+            resources.push(self.resources.find("build.ogg"));
+        }
+        for resource in resources.into_iter().flatten() {
+            match Audio::decode(&resource) {
+                Ok(sound) => {
+                    debug!("Loaded {}", resource);
+                    return sound;
+                }
+                Err(e) => warn!("Unable to load {}: {}", resource, e),
+            }
+        }
+        Audio::tone(Audio::notes(effect))
+    }
+
+    /// Decodes a whole sound file, so playing it later is cheap.
+    fn decode(resource: &Resource) -> Result<Sound, Error> {
+        let decoder = Decoder::new(Cursor::new(resource.data()?))?;
+        let (channels, rate) = (decoder.channels(), decoder.sample_rate());
+        let samples = decoder.convert_samples().collect::<Vec<f32>>();
+        Ok(SamplesBuffer::new(channels, rate, samples).buffered())
+    }
+
+    /// Frequencies in Hz and durations in milliseconds of the default sounds.
+    /// A frequency of 0 is a pause.
+    fn notes(effect: Effect) -> &'static [(u32, u32)] {
+        match effect {
+            Effect::Build => &[(523, 80), (659, 120)],
+            Effect::Excavate => &[(196, 60), (147, 100)],
+            Effect::Found => &[(659, 90), (784, 90), (1047, 180)],
+            Effect::Error => &[(220, 150), (185, 250)],
+            Effect::NewTurn => &[(880, 120)],
+            Effect::Warning => &[(988, 80), (0, 60), (988, 80)],
+            Effect::Win => &[(523, 120), (659, 120), (784, 120), (1047, 360)],
+            Effect::Lose => &[(392, 200), (330, 200), (262, 450)],
+            Effect::Invalid => &[(131, 80)],
+        }
+    }
+
+    /// Generates a mono sound playing `notes` one after another.
+    fn tone(notes: &[(u32, u32)]) -> Sound {
+        let mut samples = Vec::new();
+        for &(frequency, duration) in notes {
+            let len = (SAMPLE_RATE * duration / 1000) as usize;
+            samples.extend((0..len).map(|i| {
+                // Fading avoids clicks at the start and end of each note.
+                let fade = (i.min(len - i) as f32 / TONE_FADE as f32).min(1.0);
+                let t = i as f32 / SAMPLE_RATE as f32;
+                (2.0 * PI * frequency as f32 * t).sin() * fade * TONE_VOLUME
+            }));
+        }
+        SamplesBuffer::new(1, SAMPLE_RATE, samples).buffered()
+    }
+}
+
+impl Mixer {
+    fn new() -> Result<Mixer, Error> {
+        let device = default_output_device().ok_or(format_err!("Unable to open audio device."))?;
+        let (music_input, music) = Mixer::channel(&device);
+        let (effects_input, effects) = Mixer::channel(&device);
+        Ok(Mixer {
+            music,
+            effects,
+            music_input,
+            effects_input,
+        })
+    }
+
+    fn open() -> Result<Box<dyn Backend>, Error> {
+        Ok(Box::new(Mixer::new()?))
+    }
+
+    /// Creates a sink playing all sounds added to the returned mixer.
+    fn channel(device: &Device) -> (Arc<DynamicMixerController<f32>>, Sink) {
+        let (input, output) = dynamic_mixer::mixer(CHANNELS, SAMPLE_RATE);
+        // The mixer ends once it runs out of sounds, silence keeps it running.
+        input.add(Zero::<f32>::new(CHANNELS, SAMPLE_RATE));
+        let sink = Sink::new(device);
+        sink.append(output);
+        (input, sink)
+    }
+}
+
+impl Backend for Mixer {
+    fn play_music(&mut self, track: Track) {
+        self.music_input.add(track);
+    }
+
+    fn play_effect(&mut self, sound: &Sound) {
+        self.effects_input.add(sound.clone());
+    }
+
+    fn set_volumes(&mut self, volumes: &Volumes) {
+        self.music.set_volume(volumes.gain(Channel::Music));
+        self.effects.set_volume(volumes.gain(Channel::Effects));
+    }
+
+    fn stop(&mut self) {
+        self.music.stop();
+        self.effects.stop();
+    }
+}
+
+/// Discards all sounds, used when there is no audio device.
+struct NullBackend;
+
+impl Backend for NullBackend {
+    fn play_music(&mut self, _track: Track) {}
+    fn play_effect(&mut self, _sound: &Sound) {}
+    fn set_volumes(&mut self, _volumes: &Volumes) {}
+    fn stop(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+
+    static OPENED: AtomicBool = AtomicBool::new(false);
+
+    fn audio(enabled: bool, open: fn() -> Result<Box<dyn Backend>, Error>) -> Audio {
+        let (tx, rx) = mpsc::channel();
+        tx.send(AudioMessage::Play(Effect::Build)).unwrap();
+        tx.send(AudioMessage::Volume(Channel::Music, 0.5)).unwrap();
+        tx.send(AudioMessage::Quit).unwrap();
+        let mut audio = Audio::new(rx, Volumes::default(), enabled, Resources::new(None)).unwrap();
+        audio.open = open;
+        audio
+    }
+
+    #[test]
+    fn disabled_audio_does_not_open_device() {
+        let audio = audio(false, || panic!("Audio device opened although disabled."));
+        assert!(audio.run());
+    }
+
+    #[test]
+    fn missing_device_falls_back_to_null_backend() {
+        let audio = audio(true, || {
+            OPENED.store(true, Ordering::SeqCst);
+            Err(format_err!("Unable to open audio device."))
+        });
+        assert!(audio.run());
+        assert!(OPENED.load(Ordering::SeqCst));
+    }
 }
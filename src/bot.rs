@@ -0,0 +1,79 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::Receiver;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::message::{FromGuiMessage, ToGuiMessage};
+
+/// Prints the messages of the network client to stdout until the game ends.
+/// Used instead of the GUI in bot mode.
+pub fn run(rx: &Receiver<ToGuiMessage>) {
+    for msg in rx.iter() {
+        match msg {
+            ToGuiMessage::Status(status) => println!("{}", status),
+            ToGuiMessage::Message(title, text) => println!("{}: {}", title, text),
+            ToGuiMessage::Chat(name, message) => println!("<{}> {}", name, message),
+            ToGuiMessage::GameOver => println!("{}", tr!("game-over")),
+            ToGuiMessage::RequestQuit | ToGuiMessage::Quit => break,
+            _ => {}
+        }
+    }
+}
+
+/// Reads commands from stdin until it is closed or the client finished, then
+/// quits the game. Blocks on stdin, so it only notices the client finished
+/// after a line.
+pub fn console(tx: UnboundedSender<FromGuiMessage>) {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        if tx.is_closed() {
+            return;
+        }
+        match command(&line) {
+            Some(msg) => {
+                let quit = matches!(msg, FromGuiMessage::Quit);
+                if tx.send(msg).is_err() || quit {
+                    return;
+                }
+            }
+            None if line.trim().is_empty() => {}
+            None => warn!(
+                "Unknown command {}. Use /say <message> to chat or /quit to leave.",
+                line.trim()
+            ),
+        }
+    }
+    let _ = tx.send(FromGuiMessage::Quit);
+}
+
+/// Parses a console command, e.g. `/say hello`.
+fn command(line: &str) -> Option<FromGuiMessage> {
+    let line = line.trim();
+    if let Some(message) = line.strip_prefix("/say ") {
+        Some(FromGuiMessage::Chat(message.trim().to_string()))
+    } else if line == "/quit" {
+        Some(FromGuiMessage::Quit)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        match command("  /say  hello there ") {
+            Some(FromGuiMessage::Chat(message)) => assert_eq!(message, "hello there"),
+            c => panic!("Parsed as {:?}", c),
+        }
+        assert!(matches!(command("/quit"), Some(FromGuiMessage::Quit)));
+        assert!(command("/say").is_none());
+        assert!(command("hello").is_none());
+        assert!(command("").is_none());
+    }
+}
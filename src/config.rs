@@ -0,0 +1,180 @@
+use failure::Error;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use super::message::Channel;
+use super::theme::Palette;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Use TLS for the session server and game server connections.
+    pub tls: bool,
+    /// PEM file with CA certificates to trust instead of the system roots.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<String>,
+    /// SHA-256 fingerprint of the only server certificate to accept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempt_delay: Option<u64>,
+    /// Time limit per turn in seconds, if the server does not provide one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub turn_time: Option<u64>,
+    /// Name of the theme in `themes/` of the resource directories.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    pub palette: Palette,
+    /// Language of the messages, e.g. `de`. Defaults to the system language.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Mark hover, selection and invalid actions with shapes, not only colours.
+    pub shape_cues: bool,
+    pub volume: Volumes,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Volume {
+    pub level: f32,
+    pub muted: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Volumes {
+    pub master: Volume,
+    pub music: Volume,
+    pub effects: Volume,
+}
+
+impl Default for Volume {
+    fn default() -> Volume {
+        Volume {
+            level: 1.0,
+            muted: false,
+        }
+    }
+}
+
+impl Volumes {
+    pub fn get(&self, channel: Channel) -> Volume {
+        match channel {
+            Channel::Master => self.master,
+            Channel::Music => self.music,
+            Channel::Effects => self.effects,
+        }
+    }
+
+    pub fn get_mut(&mut self, channel: Channel) -> &mut Volume {
+        match channel {
+            Channel::Master => &mut self.master,
+            Channel::Music => &mut self.music,
+            Channel::Effects => &mut self.effects,
+        }
+    }
+
+    /// Volume a channel is played with, taking the master volume into account.
+    pub fn gain(&self, channel: Channel) -> f32 {
+        let volume = self.get(channel);
+        if volume.muted || self.master.muted {
+            0.0
+        } else if channel == Channel::Master {
+            volume.level
+        } else {
+            volume.level * self.master.level
+        }
+    }
+}
+
+impl Config {
+    /// Default location of the configuration file, following the XDG base
+    /// directory specification on Unix and using `%APPDATA%` on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = if cfg!(target_os = "windows") {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        };
+        base.map(|b| b.join("epoch-wars").join("config.json"))
+    }
+
+    /// Loads the configuration from `path`, or from the default location if no
+    /// path is given. A missing default configuration is not an error.
+    pub fn load(path: Option<&str>) -> Result<Config, Error> {
+        let path = match path {
+            Some(p) => PathBuf::from(p),
+            None => match Config::default_path() {
+                Some(p) => {
+                    if !p.exists() {
+                        return Ok(Config::default());
+                    }
+                    p
+                }
+                None => return Ok(Config::default()),
+            },
+        };
+        debug!("Loading configuration from {}", path.display());
+        let file = File::open(&path)
+            .map_err(|e| format_err!("Unable to open {}: {}", path.display(), e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format_err!("Error in configuration {}: {}", path.display(), e))
+    }
+
+    /// Saves the configuration to `path`, or to the default location if no path
+    /// is given.
+    pub fn save(&self, path: Option<&str>) -> Result<(), Error> {
+        let path = match path {
+            Some(p) => PathBuf::from(p),
+            None => Config::default_path()
+                .ok_or(format_err!("Unable to find the configuration directory."))?,
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = File::create(&path)
+            .map_err(|e| format_err!("Unable to write {}: {}", path.display(), e))?;
+        serde_json::to_writer_pretty(file, self)?;
+        debug!("Saved configuration to {}", path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volumes(master: f32, music: f32) -> Volumes {
+        let mut volumes = Volumes::default();
+        volumes.master.level = master;
+        volumes.music.level = music;
+        volumes
+    }
+
+    #[test]
+    fn gain_scales_with_master() {
+        let volumes = volumes(0.5, 0.5);
+        assert_eq!(volumes.gain(Channel::Master), 0.5);
+        assert_eq!(volumes.gain(Channel::Music), 0.25);
+        assert_eq!(volumes.gain(Channel::Effects), 0.5);
+    }
+
+    #[test]
+    fn gain_of_muted_channel() {
+        let mut volumes = volumes(0.5, 0.5);
+        volumes.music.muted = true;
+        assert_eq!(volumes.gain(Channel::Music), 0.0);
+        assert_eq!(volumes.gain(Channel::Effects), 0.5);
+        volumes.master.muted = true;
+        assert_eq!(volumes.gain(Channel::Master), 0.0);
+        assert_eq!(volumes.gain(Channel::Effects), 0.0);
+    }
+}
@@ -1,5 +1,8 @@
 use failure::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub struct Game {
@@ -11,6 +14,29 @@ pub struct Game {
     pub tower_count: u32,
     pub turn: u32,
     pub rejoin: String,
+    pub actions: TurnActions,
+    pub turn_time: Option<Duration>,
+    pub deadline: Option<Instant>,
+    pub spectator: bool,
+    pub capabilities: Vec<String>,
+    pub latency: Option<Duration>,
+    pub connection: Connection,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TurnActions {
+    pub build: bool,
+    pub excavate: bool,
+    pub end_turn: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Connection {
+    Unknown,
+    Good,
+    Fair,
+    Poor,
+    Lost,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,16 +56,104 @@ impl Game {
             rejoin: String::new(),
             tower_count: 0,
             prices: HashMap::new(),
+            actions: TurnActions::default(),
+            turn_time: None,
+            deadline: None,
+            spectator: false,
+            capabilities: Vec::new(),
+            latency: None,
+            connection: Connection::Unknown,
         }
     }
+
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.latency = Some(latency);
+        self.connection = Connection::from_latency(latency);
+    }
+
+    pub fn start_turn(&mut self, turn_time: Option<u64>) {
+        self.actions = TurnActions::default();
+        self.deadline = turn_time
+            .map(Duration::from_secs)
+            .or(self.turn_time)
+            .map(|t| Instant::now() + t);
+    }
+
+    pub fn time_left(&self) -> Option<Duration> {
+        self.deadline.map(|d| {
+            let now = Instant::now();
+            if d > now {
+                d - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug, Hash, Eq, PartialEq)]
-#[serde(rename_all = "lowercase")]
+impl Connection {
+    pub fn from_latency(latency: Duration) -> Connection {
+        if latency < Duration::from_millis(150) {
+            Connection::Good
+        } else if latency < Duration::from_millis(500) {
+            Connection::Fair
+        } else {
+            Connection::Poor
+        }
+    }
+
+    /// Number of bars shown in the connection indicator.
+    pub fn bars(&self) -> u32 {
+        match self {
+            Connection::Good => 3,
+            Connection::Fair => 2,
+            Connection::Poor => 1,
+            Connection::Unknown | Connection::Lost => 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
 pub enum Building {
     House,
     Villa,
     Tower,
+    Unknown(String),
+}
+
+impl Building {
+    pub fn name(&self) -> &str {
+        match self {
+            Building::House => "house",
+            Building::Villa => "villa",
+            Building::Tower => "tower",
+            Building::Unknown(s) => s,
+        }
+    }
+}
+
+impl fmt::Display for Building {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Building::House => write!(f, "{}", tr!("building-house")),
+            Building::Villa => write!(f, "{}", tr!("building-villa")),
+            Building::Tower => write!(f, "{}", tr!("building-tower")),
+            Building::Unknown(s) => write!(f, "{}", tr!("building-unknown", kind = s.as_str())),
+        }
+    }
+}
+
+impl Serialize for Building {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Building {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Building, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Ok(s.parse().unwrap_or(Building::Unknown(s)))
+    }
 }
 
 impl ::std::str::FromStr for Building {
@@ -1,25 +1,29 @@
 use failure::{err_msg, Error};
 
-use sdl2;
 use sdl2::event::Event;
-use sdl2::image::{LoadTexture, INIT_JPG, INIT_PNG};
+use sdl2::image::{ImageRWops, INIT_JPG, INIT_PNG};
 use sdl2::keyboard::Keycode;
 use sdl2::messagebox::{show_simple_message_box, MessageBoxFlag};
 use sdl2::mouse::MouseButton;
 use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::{TextureCreator, WindowCanvas};
-use sdl2::ttf::Sdl2TtfContext;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::{BlendMode, Texture, TextureCreator, WindowCanvas};
+use sdl2::surface::Surface;
+use sdl2::ttf::{Font, Sdl2TtfContext};
 use sdl2::video::WindowContext;
 use sdl2::Sdl;
-use std::collections::HashMap;
+use std::collections::hash_map::{Entry, HashMap};
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
 
-use super::game::{Building, Game};
-use super::message::{AudioMessage, FromGuiMessage, ToGuiMessage};
+use super::config::Volumes;
+use super::game::{Building, Connection, Game};
+use super::message::{AudioMessage, Channel, Effect, FromGuiMessage, ToGuiMessage};
+use super::resources::{Resource, Resources};
+use super::theme::{Colors, Palette, Theme, PALETTES};
 
 pub struct Gui {
     game: Arc<Mutex<Game>>,
@@ -28,46 +32,97 @@ pub struct Gui {
     canvas: WindowCanvas,
     assets: Assets,
     active: usize,
-    tx: Sender<FromGuiMessage>,
+    tx: UnboundedSender<FromGuiMessage>,
     rx: Receiver<ToGuiMessage>,
     tx_audio: Sender<AudioMessage>,
     running: bool,
+    planning: bool,
+    plan: Plan,
+    auto_end: bool,
+    timer_deadline: Option<Instant>,
+    timer_warned: bool,
+    timer_expired: bool,
+    excavations: HashMap<(u32, u32), (i32, Option<Building>)>,
+    chat: Vec<(String, String)>,
+    chat_input: Option<String>,
+    status: Vec<String>,
+    settings: Settings,
+    theme: Theme,
 }
 
+const TOOLTIP_DELAY: u64 = 500;
+/// Milliseconds a cross marks a cell after an invalid click with shape cues.
+const INVALID_CUE: u64 = 500;
+const TIMER_WARNING: u64 = 10;
+const CHAT_LINES: usize = 5;
+const CHAT_HISTORY: usize = 100;
+const STATUS_LINES: usize = 6;
+const SHORTCUTS: [&str; 4] = ["1", "2", "3", "Space"];
+
 struct Assets {
     buildings: HashMap<Building, Sprite>,
-    font: String,
+    unknown: Sprite,
+    font: Resource,
     background: Sprite,
     excavation: Sprite,
+    excavation_indicator: Sprite,
     active: Vec<Sprite>,
+    /// Decoded images of all sprites, turned into textures once the window exists.
+    images: HashMap<Resource, Surface<'static>>,
+}
+
+/// Textures of the sprite images, created once instead of on every frame.
+type Textures<'a> = HashMap<Resource, Texture<'a>>;
+
+/// Options of the GUI given on the command line or in the config file.
+pub struct GuiOptions {
+    pub size: (u32, u32),
+    pub fullscreen: bool,
+    pub planning: bool,
+    pub auto_end: bool,
+    pub settings: Settings,
+    pub theme: Theme,
+}
+
+pub struct Settings {
+    pub volumes: Volumes,
+    pub palette: Palette,
+    pub shape_cues: bool,
+    open: bool,
+    selected: usize,
+    changed: bool,
+}
+
+const VOLUME_STEP: f32 = 0.1;
+const VOLUME_CHANNELS: [(Channel, &str); 3] = [
+    (Channel::Master, "volume-master"),
+    (Channel::Music, "volume-music"),
+    (Channel::Effects, "volume-effects"),
+];
+const PALETTE_ROW: usize = 3;
+const SHAPE_CUES_ROW: usize = 4;
+const SETTINGS_ROWS: usize = 5;
+
+#[derive(Default)]
+struct Plan {
+    build: Option<((u32, u32), Building)>,
+    excavation: Option<(u32, u32)>,
 }
 
 #[derive(Clone)]
 struct Sprite {
     size: u32,
-    path: String,
+    image: Resource,
     building: Option<Building>,
     index: Option<(u32, u32)>,
     rect: Option<Rect>,
 }
 
 impl Sprite {
-    pub fn new(size: u32, path: &str) -> Sprite {
-        let base_res_path = {
-            let mut exe = ::std::env::current_exe().unwrap();
-            exe.pop();
-            if cfg!(target_os = "macos") {
-                exe.to_str().unwrap().to_string() + "/../Resources/"
-            } else if cfg!(debug_assertions) {
-                String::new()
-            } else {
-                exe.to_str().unwrap().to_string() + "/"
-            }
-        };
-        debug!("Base resource path: {}", base_res_path);
+    pub fn new(size: u32, image: Resource) -> Sprite {
         Sprite {
             size,
-            path: base_res_path + path,
+            image,
             building: None,
             index: None,
             rect: None,
@@ -78,68 +133,425 @@ impl Sprite {
         self.rect.map(|x| x.contains_point(pos)).unwrap_or(false)
     }
 
-    pub fn draw(
-        &self,
-        texture_creator: &TextureCreator<WindowContext>,
-        canvas: &mut WindowCanvas,
-    ) -> Result<(), Error> {
-        let texture = texture_creator.load_texture(&self.path).map_err(err_msg)?;
-        canvas.copy(&texture, None, self.rect).map_err(err_msg)?;
-        Ok(())
+    pub fn draw(&self, textures: &mut Textures, canvas: &mut WindowCanvas) -> Result<(), Error> {
+        self.draw_alpha(textures, canvas, 255)
     }
 
     pub fn draw_alpha(
         &self,
-        texture_creator: &TextureCreator<WindowContext>,
+        textures: &mut Textures,
         canvas: &mut WindowCanvas,
         alpha: u8,
     ) -> Result<(), Error> {
-        let mut texture = texture_creator.load_texture(&self.path).map_err(err_msg)?;
+        let texture = textures
+            .get_mut(&self.image)
+            .ok_or_else(|| format_err!("Image {} was not loaded.", self.image))?;
         texture.set_alpha_mod(alpha);
-        canvas.copy(&texture, None, self.rect).map_err(err_msg)?;
+        canvas.copy(texture, None, self.rect).map_err(err_msg)?;
+        Ok(())
+    }
+}
+
+impl Settings {
+    pub fn new(volumes: Volumes, palette: Palette, shape_cues: bool) -> Settings {
+        Settings {
+            volumes,
+            palette,
+            shape_cues,
+            open: false,
+            selected: 0,
+            changed: false,
+        }
+    }
+
+    /// Whether the settings were changed and should be saved.
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    fn change_volume(&mut self, channel: Channel, delta: f32, tx_audio: &Sender<AudioMessage>) {
+        let volume = self.volumes.get_mut(channel);
+        volume.level = (volume.level + delta).clamp(0.0, 1.0);
+        self.changed = true;
+        let _ = tx_audio.send(AudioMessage::Volume(channel, volume.level));
+    }
+
+    fn toggle_mute(&mut self, channel: Channel, tx_audio: &Sender<AudioMessage>) {
+        let volume = self.volumes.get_mut(channel);
+        volume.muted = !volume.muted;
+        self.changed = true;
+        let _ = tx_audio.send(AudioMessage::Mute(channel, volume.muted));
+    }
+
+    fn change_palette(&mut self, offset: usize) {
+        let i = PALETTES.iter().position(|p| *p == self.palette).unwrap_or(0);
+        self.palette = PALETTES[(i + offset) % PALETTES.len()];
+        self.changed = true;
+    }
+
+    /// Handles the shortcuts that work without opening the settings. Returns
+    /// whether the key was used.
+    fn shortcut(&mut self, key: Keycode, tx_audio: &Sender<AudioMessage>) -> bool {
+        match key {
+            Keycode::O => self.open = true,
+            Keycode::M => self.toggle_mute(Channel::Master, tx_audio),
+            Keycode::Plus | Keycode::Equals | Keycode::KpPlus => {
+                self.change_volume(Channel::Master, VOLUME_STEP, tx_audio)
+            }
+            Keycode::Minus | Keycode::KpMinus => {
+                self.change_volume(Channel::Master, -VOLUME_STEP, tx_audio)
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Handles a key while the settings are open.
+    fn key(&mut self, key: Keycode, tx_audio: &Sender<AudioMessage>) {
+        match key {
+            Keycode::Escape | Keycode::O => self.open = false,
+            Keycode::Up => self.selected = (self.selected + SETTINGS_ROWS - 1) % SETTINGS_ROWS,
+            Keycode::Down => self.selected = (self.selected + 1) % SETTINGS_ROWS,
+            _ if self.selected == PALETTE_ROW => match key {
+                Keycode::Left | Keycode::Minus | Keycode::KpMinus => {
+                    self.change_palette(PALETTES.len() - 1)
+                }
+                Keycode::Right
+                | Keycode::Plus
+                | Keycode::Equals
+                | Keycode::KpPlus
+                | Keycode::Return
+                | Keycode::Space => self.change_palette(1),
+                _ => {}
+            },
+            _ if self.selected == SHAPE_CUES_ROW => match key {
+                Keycode::Left | Keycode::Right | Keycode::Return | Keycode::Space => {
+                    self.shape_cues = !self.shape_cues;
+                    self.changed = true;
+                }
+                _ => {}
+            },
+            _ => {
+                let channel = VOLUME_CHANNELS[self.selected].0;
+                match key {
+                    Keycode::Left | Keycode::Minus | Keycode::KpMinus => {
+                        self.change_volume(channel, -VOLUME_STEP, tx_audio)
+                    }
+                    Keycode::Right | Keycode::Plus | Keycode::Equals | Keycode::KpPlus => {
+                        self.change_volume(channel, VOLUME_STEP, tx_audio)
+                    }
+                    Keycode::M | Keycode::Return | Keycode::Space => {
+                        self.toggle_mute(channel, tx_audio)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![tr!("settings")];
+        for (i, (channel, name)) in VOLUME_CHANNELS.iter().enumerate() {
+            let volume = self.volumes.get(*channel);
+            lines.push(format!(
+                "{} {}: {}%{}",
+                if i == self.selected { ">" } else { " " },
+                tr!(name),
+                (volume.level * 100.0).round(),
+                if volume.muted { format!(" {}", tr!("volume-muted")) } else { String::new() }
+            ));
+        }
+        let cursor = |row| if row == self.selected { ">" } else { " " };
+        lines.push(format!(
+            "{} {}",
+            cursor(PALETTE_ROW),
+            tr!("palette", palette = self.palette.name())
+        ));
+        lines.push(format!(
+            "{} {}",
+            cursor(SHAPE_CUES_ROW),
+            tr!(if self.shape_cues { "shape-cues-on" } else { "shape-cues-off" })
+        ));
+        lines.push(tr!("settings-help"));
+        lines
+    }
+}
+
+impl Plan {
+    fn end_turn(&mut self, tx: &UnboundedSender<FromGuiMessage>) -> Result<(), Error> {
+        if let Some((pos, building)) = self.build.take() {
+            tx.send(FromGuiMessage::Build(pos, building))?;
+        }
+        if let Some(pos) = self.excavation.take() {
+            tx.send(FromGuiMessage::Excavate(pos))?;
+        }
+        tx.send(FromGuiMessage::Skip)?;
         Ok(())
     }
 }
 
 impl Assets {
-    pub fn new() -> Assets {
+    pub fn building(&self, building: &Building) -> &Sprite {
+        self.buildings.get(building).unwrap_or(&self.unknown)
+    }
+
+    pub fn new(resources: &Resources, theme: &Theme) -> Result<Assets, Error> {
+        let sprite = |size, name: &str| -> Result<Sprite, Error> {
+            Ok(Sprite::new(size, resources.get(name)?))
+        };
         let buildings: HashMap<Building, Sprite> = [
-            (Building::House, Sprite::new(0, "res/house.png")),
-            (Building::Villa, Sprite::new(1, "res/villa.png")),
-            (Building::Tower, Sprite::new(0, "res/tower.png")),
+            (Building::House, sprite(0, &theme.sprites.house)?),
+            (Building::Villa, sprite(1, &theme.sprites.villa)?),
+            (Building::Tower, sprite(0, &theme.sprites.tower)?),
         ].iter()
             .cloned()
             .collect();
-        Assets {
+        let mut assets = Assets {
             buildings,
-            font: "res/font.ttf".to_string(),
-            background: Sprite::new(0, "res/bg.png"),
-            excavation: Sprite::new(0, "res/ex.png"),
+            unknown: sprite(0, &theme.sprites.unknown)?,
+            font: resources.get(&theme.font)?,
+            background: sprite(0, &theme.sprites.background)?,
+            excavation: sprite(0, &theme.sprites.excavation)?,
+            excavation_indicator: sprite(0, &theme.sprites.excavation)?,
             active: vec![
-                Sprite::new(0, "res/house.png"),
-                Sprite::new(1, "res/villa.png"),
-                Sprite::new(0, "res/tower.png"),
-                Sprite::new(0, "res/skip.png"),
+                sprite(0, &theme.sprites.house)?,
+                sprite(1, &theme.sprites.villa)?,
+                sprite(0, &theme.sprites.tower)?,
+                sprite(0, &theme.sprites.skip)?,
             ],
+            images: HashMap::new(),
+        };
+        let images = assets
+            .buildings
+            .values()
+            .chain(&assets.active)
+            .chain(vec![&assets.unknown, &assets.background, &assets.excavation])
+            .map(|sprite| sprite.image.clone())
+            .collect::<Vec<_>>();
+        for image in images {
+            if let Entry::Vacant(entry) = assets.images.entry(image) {
+                let rwops = entry.key().rwops()?;
+                let loaded = rwops.load().map_err(err_msg)?;
+                // Converting copies the pixels, so the surface outlives the file data.
+                let surface = loaded.convert(&loaded.pixel_format()).map_err(err_msg)?;
+                debug!("Loaded {}", entry.key());
+                entry.insert(surface);
+            }
         }
+        Ok(assets)
+    }
+
+    /// Creates a texture for each decoded image.
+    pub fn textures<'a>(
+        &self,
+        texture_creator: &'a TextureCreator<WindowContext>,
+    ) -> Result<Textures<'a>, Error> {
+        self.images
+            .iter()
+            .map(|(image, surface)| {
+                let texture = texture_creator.create_texture_from_surface(surface)?;
+                Ok((image.clone(), texture))
+            })
+            .collect()
+    }
+}
+
+fn draw_checkmark(canvas: &mut WindowCanvas, r: Rect, color: Color) -> Result<(), Error> {
+    let thickness = (r.width() as i32 / 12).max(1);
+    canvas.set_draw_color(color);
+    let (w, h) = (r.width() as i32, r.height() as i32);
+    for t in 0..thickness {
+        let left = Point::new(r.x() + w / 5, r.y() + h / 2 + t);
+        let bottom = Point::new(r.x() + w * 2 / 5, r.bottom() - h / 5 + t);
+        let right = Point::new(r.right() - w / 5, r.y() + h / 5 + t);
+        canvas.draw_line(left, bottom).map_err(err_msg)?;
+        canvas.draw_line(bottom, right).map_err(err_msg)?;
+    }
+    Ok(())
+}
+
+fn draw_connection(
+    canvas: &mut WindowCanvas,
+    r: Rect,
+    connection: Connection,
+    colors: &Colors,
+) -> Result<(), Error> {
+    let color = match connection {
+        Connection::Good => colors.connection_good,
+        Connection::Fair => colors.connection_fair,
+        Connection::Poor | Connection::Lost => colors.connection_poor,
+        Connection::Unknown => colors.connection_unknown,
+    };
+    let bw = (r.width() as i32 / 4).max(1);
+    for i in 0..3 {
+        let bh = (r.height() as i32 * (i + 1) / 3).max(1);
+        let bar = Rect::new(r.x() + i * bw * 3 / 2, r.bottom() - bh, bw as u32, bh as u32);
+        canvas.set_draw_color(color);
+        if (i as u32) < connection.bars() {
+            canvas.fill_rect(bar).map_err(err_msg)?;
+        } else {
+            canvas.draw_rect(bar).map_err(err_msg)?;
+        }
+    }
+    Ok(())
+}
+
+/// Draws the outline of `r` with the given width inside of it.
+fn draw_outline(canvas: &mut WindowCanvas, r: Rect, color: Color, width: u32) -> Result<(), Error> {
+    canvas.set_draw_color(color);
+    for i in 0..width.min(r.width() / 2).min(r.height() / 2) {
+        let inner = Rect::new(
+            r.x() + i as i32,
+            r.y() + i as i32,
+            r.width() - 2 * i,
+            r.height() - 2 * i,
+        );
+        canvas.draw_rect(inner).map_err(err_msg)?;
+    }
+    Ok(())
+}
+
+/// Draws a dashed outline of `r`, marking hover when shape cues are enabled.
+fn draw_dashed_outline(
+    canvas: &mut WindowCanvas,
+    r: Rect,
+    color: Color,
+    width: u32,
+) -> Result<(), Error> {
+    canvas.set_draw_color(color);
+    let width = width.min(r.width() / 2).min(r.height() / 2).max(1);
+    let dash = (r.width().min(r.height()) / 8).max(2) as i32;
+    for x in (r.x()..r.right()).step_by(2 * dash as usize) {
+        let len = dash.min(r.right() - x) as u32;
+        canvas.fill_rect(Rect::new(x, r.y(), len, width)).map_err(err_msg)?;
+        canvas.fill_rect(Rect::new(x, r.bottom() - width as i32, len, width)).map_err(err_msg)?;
+    }
+    for y in (r.y()..r.bottom()).step_by(2 * dash as usize) {
+        let len = dash.min(r.bottom() - y) as u32;
+        canvas.fill_rect(Rect::new(r.x(), y, width, len)).map_err(err_msg)?;
+        canvas.fill_rect(Rect::new(r.right() - width as i32, y, width, len)).map_err(err_msg)?;
+    }
+    Ok(())
+}
+
+/// Draws a cross over `r`, marking cells that cannot be built on or excavated.
+fn draw_cross(canvas: &mut WindowCanvas, r: Rect, color: Color) -> Result<(), Error> {
+    let thickness = (r.width() as i32 / 12).max(1);
+    canvas.set_draw_color(color);
+    let (w, h) = (r.width() as i32, r.height() as i32);
+    for t in 0..thickness {
+        let (left, right) = (r.x() + w / 4 + t, r.right() - w / 4 + t);
+        let (top, bottom) = (r.y() + h / 4, r.bottom() - h / 4);
+        canvas
+            .draw_line(Point::new(left, top), Point::new(right, bottom))
+            .map_err(err_msg)?;
+        canvas
+            .draw_line(Point::new(left, bottom), Point::new(right, top))
+            .map_err(err_msg)?;
     }
+    Ok(())
+}
+
+fn draw_tooltip(
+    canvas: &mut WindowCanvas,
+    texture_creator: &TextureCreator<WindowContext>,
+    font: &Font,
+    lines: &[String],
+    pos: (i32, i32),
+    line_height: i32,
+    colors: &Colors,
+) -> Result<(), Error> {
+    let (_, h) = canvas.window().drawable_size();
+    let height = line_height * lines.len() as i32 + line_height / 2;
+    let y = if pos.1 + line_height + height > h as i32 {
+        pos.1 - height
+    } else {
+        pos.1 + line_height
+    };
+    draw_text_box(
+        canvas,
+        texture_creator,
+        font,
+        lines,
+        (pos.0 + line_height, y),
+        line_height,
+        colors,
+    )
+}
+
+fn draw_text_box(
+    canvas: &mut WindowCanvas,
+    texture_creator: &TextureCreator<WindowContext>,
+    font: &Font,
+    lines: &[String],
+    pos: (i32, i32),
+    line_height: i32,
+    colors: &Colors,
+) -> Result<(), Error> {
+    let (w, h) = canvas.window().drawable_size();
+    let pad = line_height / 4;
+    let mut textures = Vec::new();
+    let mut width = 0;
+    for line in lines {
+        let surf = font
+            .render(line)
+            .blended(colors.text)
+            .map_err(err_msg)?;
+        let r = surf.rect();
+        let lw = (r.w * line_height) / r.h;
+        width = width.max(lw);
+        textures.push((
+            texture_creator
+                .create_texture_from_surface(&surf)
+                .map_err(err_msg)?,
+            lw,
+        ));
+    }
+    let height = line_height * lines.len() as i32;
+    let x = pos.0.min(w as i32 - width - 2 * pad).max(0);
+    let y = pos.1.min(h as i32 - height - 2 * pad).max(0);
+    let bg = Rect::new(x, y, (width + 2 * pad) as u32, (height + 2 * pad) as u32);
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(colors.box_background);
+    canvas.fill_rect(bg).map_err(err_msg)?;
+    canvas.set_draw_color(colors.box_border);
+    canvas.draw_rect(bg).map_err(err_msg)?;
+    canvas.set_blend_mode(BlendMode::None);
+    for (i, (text, lw)) in textures.iter().enumerate() {
+        let r = Rect::new(
+            x + pad,
+            y + pad + i as i32 * line_height,
+            *lw as u32,
+            line_height as u32,
+        );
+        canvas.copy(text, None, Some(r)).map_err(err_msg)?;
+    }
+    Ok(())
 }
 
 impl Gui {
     pub fn new(
-        size: (u32, u32),
-        fullscreen: bool,
-        tx: Sender<FromGuiMessage>,
+        options: GuiOptions,
+        tx: UnboundedSender<FromGuiMessage>,
         rx: Receiver<ToGuiMessage>,
         tx_audio: Sender<AudioMessage>,
         game: Arc<Mutex<Game>>,
+        resources: &Resources,
     ) -> Result<Gui, Error> {
+        let GuiOptions {
+            size,
+            fullscreen,
+            planning,
+            auto_end,
+            settings,
+            theme,
+        } = options;
         let context = sdl2::init().map_err(err_msg)?;
         let video = context.video().map_err(err_msg)?;
         let _image_context = sdl2::image::init(INIT_PNG | INIT_JPG).map_err(err_msg)?;
         let ttf_context = sdl2::ttf::init().map_err(err_msg)?;
 
-        let mut assets = Assets::new();
+        let mut assets = Assets::new(resources, &theme)?;
         assets.active[0].building = Some(Building::House);
         assets.active[1].building = Some(Building::Villa);
         assets.active[2].building = Some(Building::Tower);
@@ -152,7 +564,7 @@ impl Gui {
 
         let mut canvas = window.into_canvas().build()?;
 
-        canvas.set_draw_color(Color::RGB(50, 50, 50));
+        canvas.set_draw_color(theme.colors.background);
         canvas.clear();
         canvas.present();
         Ok(Gui {
@@ -166,39 +578,155 @@ impl Gui {
             tx_audio,
             rx,
             running: false,
+            planning,
+            plan: Plan::default(),
+            auto_end,
+            timer_deadline: None,
+            timer_warned: false,
+            timer_expired: false,
+            excavations: HashMap::new(),
+            chat: Vec::new(),
+            chat_input: None,
+            status: Vec::new(),
+            settings,
+            theme,
         })
     }
 
-    pub fn run(&mut self) {
-        if let Err(err) = self.run_res() {
-            for e in err.iter_chain() {
-                error!("{}", e);
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    fn cell_tooltip(&self, game: &Game, pos: (u32, u32)) -> Vec<String> {
+        let mut lines = vec![tr!("cell", x = pos.0, y = pos.1)];
+        for (p, building) in &game.buildings {
+            let bs = self.assets.building(building).size;
+            if pos.0 + bs >= p.0 && pos.0 <= p.0 + bs && pos.1 + bs >= p.1 && pos.1 <= p.1 + bs {
+                lines.push(tr!("cell-building", building = building.to_string()));
+            }
+        }
+        match self.excavations.get(&pos) {
+            Some((d, Some(building))) => lines.push(tr!(
+                "cell-excavated",
+                building = building.to_string(),
+                depth = *d
+            )),
+            Some((_, None)) => lines.push(tr!("cell-excavated-nothing")),
+            None => {}
+        }
+        lines
+    }
+
+    fn connection_tooltip(&self, game: &Game) -> Vec<String> {
+        let mut lines = vec![tr!(match game.connection {
+            Connection::Good => "connection-good",
+            Connection::Fair => "connection-fair",
+            Connection::Poor => "connection-poor",
+            Connection::Lost => "connection-lost",
+            Connection::Unknown => "connection-unknown",
+        })];
+        if let Some(latency) = game.latency {
+            let ms = latency.as_secs() * 1000 + latency.subsec_millis() as u64;
+            lines.push(tr!("latency", ms = ms));
+        }
+        lines
+    }
+
+    fn action_tooltip(&self, game: &Game, i: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        match self.assets.active[i].building {
+            Some(ref building) => {
+                lines.push(format!("{}", building));
+                if let Some(price) = game.prices.get(building) {
+                    lines.push(tr!("price", price = *price));
+                }
+                if *building == Building::Tower {
+                    lines.push(tr!("towers", count = game.tower_count));
+                }
+                if game.actions.build {
+                    lines.push(tr!("already-built"));
+                }
+            }
+            None => {
+                lines.push(tr!("end-turn"));
+                if self.plan.build.is_some() || self.plan.excavation.is_some() {
+                    lines.push(tr!("end-turn-planned"));
+                }
+            }
+        }
+        let key = match SHORTCUTS[i] {
+            "Space" => tr!("shortcut-space"),
+            key => key.to_string(),
+        };
+        lines.push(tr!("shortcut", key = key));
+        lines
+    }
+
+    /// Runs the GUI until the window is closed. Returns whether it finished
+    /// without an error.
+    pub fn run(&mut self) -> bool {
+        match self.run_res() {
+            Ok(()) => true,
+            Err(err) => {
+                for e in err.iter_chain() {
+                    error!("{}", e);
+                }
+                false
             }
         }
     }
 
     pub fn run_res(&mut self) -> Result<(), Error> {
         let texture_creator = self.canvas.texture_creator();
+        let mut textures = self.assets.textures(&texture_creator)?;
         let font = self
             .ttf_context
-            .load_font(&self.assets.font, 120)
+            .load_font_from_rwops(self.assets.font.rwops()?, self.theme.sizes.font)
             .map_err(err_msg)?;
         let mut event_pump = self.context.event_pump().unwrap();
+        let text_input = self.context.video().map_err(err_msg)?.text_input();
+        text_input.stop();
 
         let mut excavation_sprite = None;
+        let mut invalid_cell: Option<(Rect, Instant)> = None;
         let mut temp_sprite: Option<Sprite> = None;
         let mut mouse_pos = (0, 0);
+        let mut hover_since = Instant::now();
+        let spectator = self
+            .game
+            .lock()
+            .map_err(|_| format_err!("Error while locking Mutex."))?
+            .spectator;
         let mut building_sprites: Vec<Sprite> = Vec::new();
         let mut grid_sprites: Vec<Sprite> = Vec::new();
         'running: loop {
             let (w, h) = self.canvas.window().drawable_size();
-            let (nx, ny) = {
+            let ((nx, ny), actions, deadline, time_left) = {
                 let game = self
                     .game
                     .lock()
                     .map_err(|_| format_err!("Error while locking Mutex."))?;
-                game.size
+                (game.size, game.actions, game.deadline, game.time_left())
             };
+            if deadline != self.timer_deadline {
+                self.timer_deadline = deadline;
+                self.timer_warned = false;
+                self.timer_expired = false;
+            }
+            if let Some(t) = time_left {
+                let pending = self.running && !spectator && !actions.end_turn;
+                if pending && t <= Duration::from_secs(TIMER_WARNING) {
+                    if !self.timer_warned {
+                        self.timer_warned = true;
+                        let _ = self.tx_audio.send(AudioMessage::Play(Effect::Warning));
+                    }
+                    if self.auto_end && !self.timer_expired && t == Duration::from_secs(0) {
+                        self.timer_expired = true;
+                        info!("Turn timer expired, ending turn.");
+                        self.plan.end_turn(&self.tx)?;
+                    }
+                }
+            }
             let x = (w as f64) / (nx as f64);
             let y = (h as f64) / (ny as f64);
             let s = x.min(y).floor() as u32;
@@ -215,31 +743,150 @@ impl Gui {
                     (ew - 2 * eg) as u32,
                 ));
             }
+            self.assets.excavation_indicator.rect = Some(Rect::new(
+                eg,
+                (h as i32) - eg - 5 * ew,
+                (ew - 2 * eg) as u32,
+                (ew - 2 * eg) as u32,
+            ));
+            let connection_rect = Rect::new(
+                x_min as i32 - ag - ew / 2,
+                ag,
+                (ew / 2).max(1) as u32,
+                (ew / 2).max(1) as u32,
+            );
 
             for event in event_pump.poll_iter() {
+                if self.settings.open {
+                    match event {
+                        Event::Quit { .. } => {}
+                        Event::KeyDown {
+                            keycode: Some(key), ..
+                        } => {
+                            self.settings.key(key, &self.tx_audio);
+                            continue;
+                        }
+                        _ => continue,
+                    }
+                }
+                if let Some(input) = self.chat_input.as_mut() {
+                    match event {
+                        Event::TextInput { text, .. } => {
+                            input.push_str(&text);
+                            continue;
+                        }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::Backspace),
+                            ..
+                        } => {
+                            input.pop();
+                            continue;
+                        }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::Return),
+                            ..
+                        } => {
+                            if !input.trim().is_empty() {
+                                self.tx.send(FromGuiMessage::Chat(input.trim().to_string()))?;
+                            }
+                            self.chat_input = None;
+                            text_input.stop();
+                            continue;
+                        }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::Escape),
+                            ..
+                        } => {
+                            self.chat_input = None;
+                            text_input.stop();
+                            continue;
+                        }
+                        Event::KeyDown { .. } => continue,
+                        _ => {}
+                    }
+                }
                 match event {
                     Event::Quit { .. }
                     | Event::KeyDown {
                         keycode: Some(Keycode::Escape),
                         ..
                     } => {
-                        self.tx.send(FromGuiMessage::Quit)?;
+                        let _ = self.tx.send(FromGuiMessage::Quit);
                         break 'running;
                     }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Return),
+                        ..
+                    } => {
+                        self.chat_input = Some(String::new());
+                        text_input.start();
+                    }
+                    Event::KeyDown {
+                        keycode: Some(key), ..
+                    } if self.settings.shortcut(key, &self.tx_audio) => {}
+                    Event::KeyDown { .. } | Event::MouseButtonUp { .. } if spectator => {}
+                    Event::KeyDown {
+                        keycode: Some(key),
+                        ..
+                    } => match key {
+                        Keycode::Num1 | Keycode::Kp1 => self.active = 0,
+                        Keycode::Num2 | Keycode::Kp2 => self.active = 1,
+                        Keycode::Num3 | Keycode::Kp3 => self.active = 2,
+                        Keycode::Space => self.plan.end_turn(&self.tx)?,
+                        Keycode::P => {
+                            self.planning = !self.planning;
+                            // Leaving planning mode discards the plan, so it is
+                            // neither drawn nor sent at the end of the turn.
+                            if !self.planning {
+                                if self.plan.build.take().is_some() {
+                                    temp_sprite = None;
+                                }
+                                if self.plan.excavation.take().is_some() {
+                                    excavation_sprite = None;
+                                }
+                            }
+                        }
+                        Keycode::Backspace | Keycode::Delete => {
+                            if self.plan.build.take().is_some() {
+                                temp_sprite = None;
+                            }
+                            if self.plan.excavation.take().is_some() {
+                                excavation_sprite = None;
+                            }
+                        }
+                        _ => {}
+                    },
                     Event::MouseButtonUp {
                         mouse_btn: MouseButton::Right,
                         x,
                         y,
                         ..
                     } => {
+                        if actions.excavate {
+                            if let Some(cell) = grid_sprites.iter().find(|s| s.contains((x, y))) {
+                                let _ = self.tx_audio.send(AudioMessage::Play(Effect::Invalid));
+                                invalid_cell = cell.rect.map(|r| (r, Instant::now()));
+                            }
+                            continue;
+                        }
                         for sprite in &grid_sprites {
                             if sprite.contains((x, y)) {
                                 if let Some(pos) = sprite.index {
                                     let mut s = self.assets.excavation.clone();
                                     s.index = Some(pos);
                                     s.rect = sprite.rect.clone();
-                                    excavation_sprite = Some(s);
-                                    self.tx.send(FromGuiMessage::Excavate(pos))?;
+                                    if self.planning {
+                                        if self.plan.excavation == Some(pos) {
+                                            self.plan.excavation = None;
+                                            excavation_sprite = None;
+                                        } else {
+                                            self.plan.excavation = Some(pos);
+                                            excavation_sprite = Some(s);
+                                        }
+                                    } else {
+                                        excavation_sprite = Some(s);
+                                        self.tx.send(FromGuiMessage::Excavate(pos))?;
+                                    }
                                 }
                             }
                         }
@@ -255,20 +902,32 @@ impl Gui {
                                 if i < 3 {
                                     self.active = i;
                                 } else if i == 3 {
-                                    self.tx.send(FromGuiMessage::Skip)?;
+                                    self.plan.end_turn(&self.tx)?;
                                 }
                             }
                         }
+                        if actions.build {
+                            if grid_sprites.iter().any(|s| s.contains((x, y))) {
+                                let _ = self.tx_audio.send(AudioMessage::Play(Effect::Invalid));
+                            }
+                            continue;
+                        }
                         for sprite in &grid_sprites {
                             if sprite.contains((x, y)) {
                                 if let Some(pos) = sprite.index {
                                     let asprite = self.assets.active[self.active].clone();
                                     if let Some(building) = asprite.building {
+                                        let planned = Some((pos, building.clone()));
+                                        if self.planning && self.plan.build == planned {
+                                            self.plan.build = None;
+                                            temp_sprite = None;
+                                            continue;
+                                        }
                                         let bs = asprite.size;
                                         temp_sprite = Some(Sprite {
                                             size: bs,
                                             index: Some(pos),
-                                            path: self.assets.buildings[&building].path.clone(),
+                                            image: self.assets.building(&building).image.clone(),
                                             building: Some(building.clone()),
                                             rect: Some(Rect::new(
                                                 (x_min + s * (pos.0 - bs)) as i32,
@@ -277,89 +936,154 @@ impl Gui {
                                                 s * (1 + 2 * bs),
                                             )),
                                         });
-                                        self.tx.send(FromGuiMessage::Build(pos, building.clone()))?;
-                                        self.tx_audio.send(AudioMessage::Build)?;
+                                        if self.planning {
+                                            self.plan.build = planned;
+                                        } else {
+                                            self.tx.send(FromGuiMessage::Build(pos, building))?;
+                                        }
                                     }
                                 }
                             }
                         }
                     }
-                    Event::MouseMotion { x, y, .. } => mouse_pos = (x, y),
+                    Event::MouseMotion { x, y, .. } => {
+                        mouse_pos = (x, y);
+                        hover_since = Instant::now();
+                    }
                     _ => {}
                 }
             }
             thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
-            self.canvas.set_draw_color(Color::RGB(50, 50, 50));
+            let colors = self.theme.colors.with_palette(self.settings.palette);
+            let cues = self.settings.shape_cues;
+            self.canvas.set_draw_color(colors.background);
             self.canvas.clear();
+            let hover = colors.hover;
+            let selected = colors.selected;
+            let done = colors.done;
+            let outline = self.theme.sizes.outline;
+            let alpha = self.theme.sizes.planned_alpha;
             if self.running {
+                let mut tooltip = None;
                 for sprite in &grid_sprites {
-                    sprite.draw(&texture_creator, &mut self.canvas)?;
+                    sprite.draw(&mut textures, &mut self.canvas)?;
                     if let Some(r) = sprite.rect {
-                        if sprite.contains(mouse_pos) {
-                            self.canvas.set_draw_color(Color::RGB(255, 0, 0));
-                            let r = r.clone();
-                            self.canvas.draw_rect(r).map_err(err_msg)?;
+                        if sprite.contains(mouse_pos) && cues {
+                            draw_dashed_outline(&mut self.canvas, r, hover, outline)?;
+                            if !spectator && actions.build {
+                                draw_cross(&mut self.canvas, r, hover)?;
+                            }
+                        } else if sprite.contains(mouse_pos) {
+                            draw_outline(&mut self.canvas, r, hover, outline)?;
                         }
                     }
                 }
+                if let Some((r, since)) = invalid_cell {
+                    if since.elapsed() >= Duration::from_millis(INVALID_CUE) {
+                        invalid_cell = None;
+                    } else if cues {
+                        draw_cross(&mut self.canvas, r, hover)?;
+                    }
+                }
                 if let Some(sprite) = &excavation_sprite {
-                    sprite.draw(&texture_creator, &mut self.canvas)?;
+                    if self.plan.excavation.is_some() {
+                        sprite.draw_alpha(&mut textures, &mut self.canvas, alpha)?;
+                    } else {
+                        sprite.draw(&mut textures, &mut self.canvas)?;
+                    }
                 }
                 if let Ok(game) = self.game.lock() {
-                    for (i, sprite) in self.assets.active.iter().enumerate() {
-                        if let Some(r) = sprite.rect {
-                            if i == self.active {
-                                self.canvas.set_draw_color(Color::RGB(0, 200, 0));
-                                let r = r.clone();
-                                self.canvas.draw_rect(r).map_err(err_msg)?;
-                            } else if sprite.contains(mouse_pos) {
-                                self.canvas.set_draw_color(Color::RGB(255, 0, 0));
-                                let r = r.clone();
-                                self.canvas.draw_rect(r).map_err(err_msg)?;
+                    if !spectator {
+                        for (i, sprite) in self.assets.active.iter().enumerate() {
+                            if let Some(r) = sprite.rect {
+                                if i == self.active && cues {
+                                    draw_outline(&mut self.canvas, r, selected, 3 * outline)?;
+                                } else if i == self.active {
+                                    draw_outline(&mut self.canvas, r, selected, outline)?;
+                                } else if sprite.contains(mouse_pos) && cues {
+                                    draw_dashed_outline(&mut self.canvas, r, hover, outline)?;
+                                } else if sprite.contains(mouse_pos) {
+                                    draw_outline(&mut self.canvas, r, hover, outline)?;
+                                }
+                                if i < 3 {
+                                    if let Some(building) = &sprite.building {
+                                        if let Some(price) = game.prices.get(building) {
+                                            let s = if i == 2 {
+                                                format!("{} ({})", price, game.tower_count)
+                                            } else {
+                                                format!("{}", price)
+                                            };
+                                            let surf = font
+                                                .render(&s)
+                                                .blended(colors.text)
+                                                .map_err(err_msg)?;
+                                            let text = texture_creator
+                                                .create_texture_from_surface(&surf)
+                                                .unwrap();
+                                            let mut rt = surf.rect().clone();
+                                            rt.x = ew + 3 * eg;
+                                            rt.y = r.y;
+                                            rt.w = (rt.w * (ew - 2 * eg)) / rt.h;
+                                            rt.h = ew - 2 * eg;
+                                            self.canvas
+                                                .copy(&text, None, Some(rt))
+                                                .map_err(err_msg)?;
+                                        }
+                                    }
+                                }
                             }
-                            if i < 3 {
-                                if let Some(building) = &sprite.building {
-                                    if let Some(price) = game.prices.get(building) {
-                                        let s = if i == 2 {
-                                            format!("{} ({})", price, game.tower_count)
-                                        } else {
-                                            format!("{}", price)
-                                        };
-                                        let surf = font
-                                            .render(&s)
-                                            .blended(Color::RGB(255, 255, 255))
-                                            .map_err(err_msg)?;
-                                        let text = texture_creator
-                                            .create_texture_from_surface(&surf)
-                                            .unwrap();
-                                        let mut rt = surf.rect().clone();
-                                        rt.x = ew + 3 * eg;
-                                        rt.y = r.y;
-                                        rt.w = (rt.w * (ew - 2 * eg)) / rt.h;
-                                        rt.h = ew - 2 * eg;
-                                        self.canvas.copy(&text, None, Some(rt)).map_err(err_msg)?;
+                            if i < 3 && actions.build {
+                                sprite.draw_alpha(&mut textures, &mut self.canvas, alpha)?;
+                                let planned = temp_sprite.as_ref().and_then(|t| t.building.clone());
+                                if sprite.building == planned {
+                                    if let Some(r) = sprite.rect {
+                                        draw_checkmark(&mut self.canvas, r, done)?;
                                     }
                                 }
+                            } else {
+                                sprite.draw(&mut textures, &mut self.canvas)?
+                            }
+                        }
+                        let indicator = &self.assets.excavation_indicator;
+                        if actions.excavate {
+                            indicator.draw_alpha(&mut textures, &mut self.canvas, alpha)?;
+                            if let Some(r) = indicator.rect {
+                                draw_checkmark(&mut self.canvas, r, done)?;
                             }
+                        } else {
+                            indicator.draw(&mut textures, &mut self.canvas)?;
+                        }
+                        if let Some(sprite) = &temp_sprite {
+                            sprite.draw_alpha(&mut textures, &mut self.canvas, alpha)?;
                         }
-                        sprite.draw(&texture_creator, &mut self.canvas)?
                     }
-                    if let Some(sprite) = &temp_sprite {
-                        sprite.draw_alpha(&texture_creator, &mut self.canvas, 100)?;
+                    let mut strings = vec![if spectator {
+                        tr!("turn-spectating", turn = game.turn)
+                    } else if self.planning {
+                        tr!("turn-planning", turn = game.turn)
+                    } else {
+                        tr!("turn", turn = game.turn)
+                    }];
+                    if game.connection == Connection::Lost {
+                        strings.push(tr!("reconnecting"));
+                    }
+                    if let Some(t) = time_left {
+                        let secs = t.as_secs() + if t.subsec_nanos() > 0 { 1 } else { 0 };
+                        let time = format!("{}:{:02}", secs / 60, secs % 60);
+                        strings.push(tr!("time-left", time = time));
                     }
-                    let mut strings = vec![format!("Turn {}", game.turn)];
                     let mut f = ::std::f64::INFINITY;
                     let mut h = 0;
                     for sprite in &building_sprites {
-                        sprite.draw(&texture_creator, &mut self.canvas)?;
+                        sprite.draw(&mut textures, &mut self.canvas)?;
                     }
                     for score in &game.scores {
                         let score_str = format!("{:3}: {}", score.score, score.name);
                         let surf = font
                             .render(&score_str)
-                            .blended(Color::RGB(255, 255, 255))
+                            .blended(colors.text)
                             .map_err(err_msg)?;
-                        let mut r = surf.rect();
+                        let r = surf.rect();
                         f = f.min((x_min as f64 - ag as f64) / (r.w as f64));
                         f = f.min((s as f64) / (r.h as f64));
                         h = h.max((r.h as f64).round() as i32);
@@ -370,7 +1094,7 @@ impl Gui {
                         if s.len() > 0 {
                             let surf = font
                                 .render(&s)
-                                .blended(Color::RGB(255, 255, 255))
+                                .blended(colors.text)
                                 .map_err(err_msg)?;
                             let text = texture_creator.create_texture_from_surface(&surf).unwrap();
                             let mut r = surf.rect();
@@ -382,11 +1106,74 @@ impl Gui {
                             self.canvas.copy(&text, None, Some(r)).map_err(err_msg)?;
                         }
                     }
+                    draw_connection(
+                        &mut self.canvas,
+                        connection_rect,
+                        game.connection,
+                        &colors,
+                    )?;
+                    if hover_since.elapsed() >= Duration::from_millis(TOOLTIP_DELAY) {
+                        let mut lines = Vec::new();
+                        for (i, sprite) in self.assets.active.iter().enumerate() {
+                            if !spectator && sprite.contains(mouse_pos) {
+                                lines = self.action_tooltip(&game, i);
+                            }
+                        }
+                        if connection_rect.contains_point(mouse_pos) {
+                            lines = self.connection_tooltip(&game);
+                        }
+                        if !spectator && self.assets.excavation_indicator.contains(mouse_pos) {
+                            lines = vec![tr!("excavate"), tr!("excavate-hint")];
+                            if game.actions.excavate {
+                                lines.push(tr!("already-excavated"));
+                            }
+                        }
+                        for sprite in &grid_sprites {
+                            if sprite.contains(mouse_pos) {
+                                if let Some(pos) = sprite.index {
+                                    lines = self.cell_tooltip(&game, pos);
+                                }
+                            }
+                        }
+                        tooltip = Some(lines).filter(|l| !l.is_empty());
+                    }
+                }
+                let line_height = self.theme.sizes.line_height(h);
+                let mut chat_lines = self
+                    .chat
+                    .iter()
+                    .skip(self.chat.len().saturating_sub(CHAT_LINES))
+                    .map(|(name, message)| format!("{}: {}", name, message))
+                    .collect::<Vec<_>>();
+                if let Some(input) = &self.chat_input {
+                    chat_lines.push(format!("> {}_", input));
+                }
+                if !chat_lines.is_empty() {
+                    draw_text_box(
+                        &mut self.canvas,
+                        &texture_creator,
+                        &font,
+                        &chat_lines,
+                        (x_min as i32, h as i32),
+                        line_height,
+                        &colors,
+                    )?;
+                }
+                if let Some(lines) = tooltip {
+                    draw_tooltip(
+                        &mut self.canvas,
+                        &texture_creator,
+                        &font,
+                        &lines,
+                        mouse_pos,
+                        line_height,
+                        &colors,
+                    )?;
                 }
             } else {
                 let surf = font
-                    .render("Waiting for server ...")
-                    .blended(Color::RGB(255, 255, 255))
+                    .render(&tr!("waiting-for-server"))
+                    .blended(colors.text)
                     .map_err(err_msg)?;
                 let text = texture_creator.create_texture_from_surface(&surf).unwrap();
                 let mut r = surf.rect();
@@ -395,42 +1182,86 @@ impl Gui {
                 r.x = (w as i32 - r.w) / 2;
                 r.y = (h as i32 - r.h) / 2;
                 self.canvas.copy(&text, None, Some(r)).map_err(err_msg)?;
+                if !self.status.is_empty() {
+                    draw_text_box(
+                        &mut self.canvas,
+                        &texture_creator,
+                        &font,
+                        &self.status,
+                        (r.x, r.y + r.h),
+                        self.theme.sizes.line_height(h),
+                        &colors,
+                    )?;
+                }
+            }
+            if self.settings.open {
+                draw_text_box(
+                    &mut self.canvas,
+                    &texture_creator,
+                    &font,
+                    &self.settings.lines(),
+                    (w as i32 / 4, h as i32 / 4),
+                    self.theme.sizes.line_height(h),
+                    &colors,
+                )?;
             }
             self.canvas.present();
             if let Ok(msg) = self.rx.try_recv() {
                 trace!("Got message: {:?}", msg);
                 match msg {
                     ToGuiMessage::Start => self.running = true,
+                    ToGuiMessage::GameOver => {}
+                    ToGuiMessage::Status(status) => {
+                        self.status.push(status);
+                        if self.status.len() > STATUS_LINES {
+                            self.status.remove(0);
+                        }
+                    }
+                    ToGuiMessage::Chat(name, message) => {
+                        self.chat.push((name, message));
+                        if self.chat.len() > CHAT_HISTORY {
+                            self.chat.remove(0);
+                        }
+                    }
                     ToGuiMessage::Message(t, s) => show_simple_message_box(
                         MessageBoxFlag::empty(),
                         &t,
                         &s,
                         self.canvas.window(),
                     )?,
-                    ToGuiMessage::ExcavateResult(d, b, p) => match b {
-                        Some(building) => show_simple_message_box(
-                            MessageBoxFlag::empty(),
-                            "Excavation Results",
-                            &format!(
-                                "Found {:?} at depth {} on position {}, {}.",
-                                building, d, p.0, p.1
-                            ),
-                            self.canvas.window(),
-                        )?,
-                        None => show_simple_message_box(
-                            MessageBoxFlag::empty(),
-                            "Excavation Results",
-                            &format!("Found nothing at position {}, {}.", p.0, p.1),
-                            self.canvas.window(),
-                        )?,
-                    },
-                    ToGuiMessage::ClearBuilding => temp_sprite = None,
+                    ToGuiMessage::ExcavateResult(d, b, p) => {
+                        self.excavations.insert(p, (d, b.clone()));
+                        match b {
+                            Some(building) => show_simple_message_box(
+                                MessageBoxFlag::empty(),
+                                &tr!("excavation-results"),
+                                &tr!(
+                                    "excavation-found",
+                                    building = building.to_string(),
+                                    depth = d,
+                                    x = p.0,
+                                    y = p.1
+                                ),
+                                self.canvas.window(),
+                            )?,
+                            None => show_simple_message_box(
+                                MessageBoxFlag::empty(),
+                                &tr!("excavation-results"),
+                                &tr!("excavation-nothing", x = p.0, y = p.1),
+                                self.canvas.window(),
+                            )?,
+                        }
+                    }
+                    ToGuiMessage::ClearBuilding => {
+                        self.plan.build = None;
+                        temp_sprite = None;
+                    }
                     ToGuiMessage::SetBuilding(pos, building) => {
-                        let bs = self.assets.buildings[&building].size;
+                        let bs = self.assets.building(&building).size;
                         temp_sprite = Some(Sprite {
                             size: bs,
                             index: Some(pos),
-                            path: self.assets.buildings[&building].path.clone(),
+                            image: self.assets.building(&building).image.clone(),
                             building: Some(building.clone()),
                             rect: Some(Rect::new(
                                 (x_min + s * (pos.0 - bs)) as i32,
@@ -440,9 +1271,12 @@ impl Gui {
                             )),
                         })
                     }
-                    ToGuiMessage::ClearExcavate => excavation_sprite = None,
+                    ToGuiMessage::ClearExcavate => {
+                        self.plan.excavation = None;
+                        excavation_sprite = None;
+                    }
                     ToGuiMessage::RequestQuit => {
-                        self.tx.send(FromGuiMessage::Quit)?;
+                        let _ = self.tx.send(FromGuiMessage::Quit);
                         break 'running;
                     }
                     ToGuiMessage::UpdateGrid => {
@@ -467,11 +1301,11 @@ impl Gui {
                                 .buildings
                                 .iter()
                                 .map(|(pos, building)| {
-                                    let bs = self.assets.buildings[&building].size;
+                                    let bs = self.assets.building(&building).size;
                                     Sprite {
                                         size: bs,
                                         index: Some(*pos),
-                                        path: self.assets.buildings[&building].path.clone(),
+                                        image: self.assets.building(&building).image.clone(),
                                         building: Some(building.clone()),
                                         rect: Some(Rect::new(
                                             (x_min + s * (pos.0 - bs)) as i32,
@@ -485,7 +1319,10 @@ impl Gui {
                                 .clone();
                         }
                     }
-                    ToGuiMessage::Quit => break 'running,
+                    ToGuiMessage::Quit => {
+                        let _ = self.tx.send(FromGuiMessage::Quit);
+                        break 'running;
+                    }
                 }
             }
         }
@@ -0,0 +1,108 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+use super::resources::Resources;
+
+/// English messages, compiled in so every message has a fallback.
+const FALLBACK: &str = include_str!("../res/locales/en.ftl");
+
+static CATALOG: OnceLock<Vec<FluentBundle<FluentResource>>> = OnceLock::new();
+
+/// Translates the message `id` of the message catalogs, e.g.
+/// `tr!("turn", turn = 3)`.
+macro_rules! tr {
+    ($id:expr) => {
+        $crate::locale::translate($id, None)
+    };
+    ($id:expr, $($name:ident = $value:expr),+ $(,)*) => {{
+        let mut args = ::fluent_bundle::FluentArgs::new();
+        $(args.set(stringify!($name), $value);)+
+        $crate::locale::translate($id, Some(&args))
+    }};
+}
+
+/// Loads the message catalog `locales/<language>.ftl` from the resource
+/// directories. Without a language the one of the system is used, messages
+/// missing in the catalog are shown in English.
+pub fn init(resources: &Resources, language: Option<&str>) {
+    let language = language
+        .map(|l| l.to_string())
+        .or_else(sys_locale::get_locale)
+        .unwrap_or_else(|| "en".to_string());
+    let mut bundles = Vec::new();
+    bundles.extend(load(resources, &language));
+    bundles.extend(bundle("en", FALLBACK.to_string()));
+    if CATALOG.set(bundles).is_err() {
+        warn!("Message catalog is already loaded.");
+    }
+}
+
+/// Loads the catalog of the full language tag, e.g. `de-AT`, or else of the
+/// language alone.
+fn load(resources: &Resources, language: &str) -> Option<FluentBundle<FluentResource>> {
+    // Locales like de_DE.UTF-8 are used on Unix.
+    let tag = language.split('.').next().unwrap_or("").replace('_', "-");
+    let primary = tag.split('-').next().unwrap_or("").to_string();
+    for name in &[tag, primary] {
+        if name.is_empty() || name == "en" {
+            continue;
+        }
+        let resource = match resources.find(&format!("locales/{}.ftl", name)) {
+            Some(resource) => resource,
+            None => continue,
+        };
+        let source = match resource.data() {
+            Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+            Err(e) => {
+                warn!("{}", e);
+                continue;
+            }
+        };
+        debug!("Loaded message catalog {}", resource);
+        return bundle(name, source);
+    }
+    if !language.starts_with("en") {
+        info!("No messages for language {}, using English.", language);
+    }
+    None
+}
+
+fn bundle(language: &str, source: String) -> Option<FluentBundle<FluentResource>> {
+    let id = language.parse::<LanguageIdentifier>().unwrap_or_default();
+    let resource = match FluentResource::try_new(source) {
+        Ok(resource) => resource,
+        Err((resource, errors)) => {
+            warn!("Errors in messages for {}: {:?}", language, errors);
+            resource
+        }
+    };
+    let mut bundle = FluentBundle::new_concurrent(vec![id]);
+    // Isolation marks around arguments are not rendered by the font.
+    bundle.set_use_isolating(false);
+    if let Err(errors) = bundle.add_resource(resource) {
+        warn!("Errors in messages for {}: {:?}", language, errors);
+    }
+    Some(bundle)
+}
+
+/// Formats the message `id`, falling back to English and then to the id
+/// itself. Use the `tr!` macro instead of calling this directly.
+pub fn translate(id: &str, args: Option<&FluentArgs>) -> String {
+    let bundles = CATALOG.get().map(|b| b.as_slice()).unwrap_or(&[]);
+    for bundle in bundles {
+        let pattern = match bundle.get_message(id).and_then(|m| m.value()) {
+            Some(pattern) => pattern,
+            None => continue,
+        };
+        let mut errors = Vec::new();
+        let text = bundle.format_pattern(pattern, args, &mut errors);
+        if !errors.is_empty() {
+            warn!("Errors formatting message {}: {:?}", id, errors);
+        }
+        return text.into_owned();
+    }
+    warn!("Missing message {}", id);
+    id.to_string()
+}
@@ -4,31 +4,74 @@
 extern crate serde_derive;
 #[macro_use]
 extern crate failure;
+extern crate fluent_bundle;
+extern crate futures_util;
 #[macro_use]
 extern crate log;
 
 extern crate clap;
+extern crate rand;
 extern crate rodio;
+extern crate rustls;
+extern crate rustls_pemfile;
 extern crate sdl2;
 extern crate serde;
 extern crate serde_json;
+extern crate sha2;
+extern crate socket2;
 extern crate stderrlog;
+extern crate sys_locale;
+extern crate tokio;
+extern crate tokio_rustls;
+extern crate tokio_tungstenite;
+extern crate tokio_util;
+extern crate unic_langid;
+extern crate webpki_roots;
 
+#[macro_use]
+mod locale;
 mod audio;
+mod bot;
+mod config;
 mod game;
 mod gui;
 mod message;
+mod music;
 mod network;
+mod proxy;
+mod resources;
+mod theme;
+mod tls;
+mod transport;
 
 use audio::*;
+use config::Config;
 use game::*;
 use gui::*;
+use message::{AudioMessage, FromGuiMessage};
 use network::*;
+use resources::Resources;
+use theme::Theme;
+use tls::TlsOptions;
+use transport::ConnectOptions;
 
 use clap::{App, Arg, ArgMatches};
 use failure::Error;
+use std::env;
+use std::process;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::{mpsc, Arc, Mutex};
-use std::thread;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Exit code for errors during start up or in the GUI.
+const EXIT_ERROR: i32 = 1;
+/// Exit code if the connection to the server failed.
+const EXIT_NETWORK: i32 = 2;
+/// Exit code if a thread did not shut down in time or panicked.
+const EXIT_SHUTDOWN: i32 = 3;
+/// Seconds to wait for each thread when shutting down.
+const SHUTDOWN_TIMEOUT: u64 = 6;
 
 fn main() {
     let matches = App::new("Epoch Wars")
@@ -65,6 +108,29 @@ fn main() {
                 .long("full")
                 .help("Show window in fullscreen."),
         )
+        .arg(
+            Arg::with_name("plan")
+                .short("p")
+                .long("plan")
+                .help("Start in planning mode, sending actions only at the end of the turn."),
+        )
+        .arg(
+            Arg::with_name("turn_time")
+                .long("turn-time")
+                .takes_value(true)
+                .help("Time limit per turn in seconds, if the server does not provide one."),
+        )
+        .arg(
+            Arg::with_name("auto_end")
+                .long("auto-end")
+                .help("End the turn automatically when the turn timer runs out."),
+        )
+        .arg(
+            Arg::with_name("spectate")
+                .long("spectate")
+                .conflicts_with("token")
+                .help("Watch the game as a spectator without joining as a player."),
+        )
         .arg(
             Arg::with_name("token")
                 .short("t")
@@ -72,10 +138,80 @@ fn main() {
                 .takes_value(true)
                 .help("Rejoin token."),
         )
+        .arg(
+            Arg::with_name("tls")
+                .long("tls")
+                .help("Use TLS for the session server and game server connections."),
+        )
+        .arg(
+            Arg::with_name("ca_file")
+                .long("ca-file")
+                .takes_value(true)
+                .help("PEM file with CA certificates to trust instead of the system roots."),
+        )
+        .arg(
+            Arg::with_name("pin")
+                .long("pin")
+                .takes_value(true)
+                .help("Accept only the server certificate with this SHA-256 fingerprint."),
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .long("proxy")
+                .takes_value(true)
+                .help("Proxy to connect through, e.g. socks5://host:1080 or http://host:8080."),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .help("Timeout for each connection attempt in milliseconds."),
+        )
+        .arg(
+            Arg::with_name("attempt_delay")
+                .long("attempt-delay")
+                .takes_value(true)
+                .help("Delay in milliseconds before trying the next server address."),
+        )
+        .arg(
+            Arg::with_name("bot")
+                .long("bot")
+                .help("Run without a window, reading /say <message> and /quit from stdin."),
+        )
+        .arg(
+            Arg::with_name("no_audio")
+                .long("no-audio")
+                .help("Disable music and sound effects."),
+        )
+        .arg(
+            Arg::with_name("resources")
+                .long("resources")
+                .takes_value(true)
+                .help("Directory with sprites, fonts and sounds overriding the installed ones."),
+        )
+        .arg(
+            Arg::with_name("theme")
+                .long("theme")
+                .takes_value(true)
+                .help("Visual theme, e.g. high-contrast."),
+        )
+        .arg(
+            Arg::with_name("language")
+                .long("language")
+                .takes_value(true)
+                .help("Language of the messages, e.g. en or de."),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .help("Configuration file."),
+        )
         .arg(
             Arg::with_name("address")
                 .takes_value(true)
-                .help("Address of server"),
+                .help("Address of server. Use ws:// or wss:// for a WebSocket connection."),
         )
         .get_matches();
     stderrlog::new()
@@ -83,21 +219,41 @@ fn main() {
         .init()
         .unwrap();
 
-    if let Err(err) = main_res(matches) {
-        for e in err.iter_chain() {
-            error!("{}", e);
+    let code = match main_res(matches) {
+        Ok(code) => code,
+        Err(err) => {
+            for e in err.iter_chain() {
+                error!("{}", e);
+            }
+            EXIT_ERROR
         }
-    }
+    };
+    process::exit(code);
 }
 
-fn main_res(matches: ArgMatches) -> Result<(), Error> {
+fn main_res(matches: ArgMatches) -> Result<i32, Error> {
+    let mut config = Config::load(matches.value_of("config"))?;
+    let resources = Resources::new(matches.value_of("resources"));
+    locale::init(
+        &resources,
+        matches.value_of("language").or(config.language.as_deref()),
+    );
     let address = matches
         .value_of("address")
         .unwrap_or("epoch-wars-session.blahut.tech:4200");
     let name = matches.value_of("name").unwrap_or("Noname");
     let direct = matches.is_present("direct");
     let fullscreen = matches.is_present("fullscreen");
-    let game = Arc::new(Mutex::new(Game::new()));
+    let planning = matches.is_present("plan");
+    let auto_end = matches.is_present("auto_end");
+    let mut game = Game::new();
+    game.spectator = matches.is_present("spectate");
+    if let Some(t) = matches.value_of("turn_time") {
+        game.turn_time = Some(Duration::from_secs(t.parse()?));
+    } else if let Some(t) = config.turn_time {
+        game.turn_time = Some(Duration::from_secs(t));
+    }
+    let game = Arc::new(Mutex::new(game));
     let size = {
         if let Some(mut values) = matches.values_of("size") {
             (
@@ -115,22 +271,151 @@ fn main_res(matches: ArgMatches) -> Result<(), Error> {
         }
     };
 
-    let (tx_gui, rx_net) = mpsc::channel();
+    let ca_file = matches
+        .value_of("ca_file")
+        .map(|x| x.to_string())
+        .or(config.ca_file.clone());
+    let pin = matches
+        .value_of("pin")
+        .map(|x| x.to_string())
+        .or(config.pin.clone());
+    let tls = TlsOptions {
+        enabled: matches.is_present("tls") || config.tls || ca_file.is_some() || pin.is_some(),
+        ca_file,
+        pin,
+    };
+    let proxy = matches
+        .value_of("proxy")
+        .map(|x| x.to_string())
+        .or(config.proxy.clone())
+        .or_else(|| env::var("ALL_PROXY").ok())
+        .or_else(|| env::var("all_proxy").ok())
+        .filter(|x| !x.is_empty());
+    let mut options = ConnectOptions {
+        tls,
+        proxy: match proxy {
+            Some(p) => Some(p.parse()?),
+            None => None,
+        },
+        ..ConnectOptions::default()
+    };
+    if let Some(t) = matches.value_of("timeout") {
+        options.timeout = Duration::from_millis(t.parse()?);
+    } else if let Some(t) = config.connect_timeout {
+        options.timeout = Duration::from_millis(t);
+    }
+    if let Some(t) = matches.value_of("attempt_delay") {
+        options.attempt_delay = Duration::from_millis(t.parse()?);
+    } else if let Some(t) = config.attempt_delay {
+        options.attempt_delay = Duration::from_millis(t);
+    }
+
+    let theme = match matches.value_of("theme").or(config.theme.as_deref()) {
+        Some(name) => Theme::load(&resources, name)?,
+        None => Theme::default(),
+    };
+
+    let (tx_gui, rx_net) = tokio::sync::mpsc::unbounded_channel();
     let (tx_net, rx_gui) = mpsc::channel();
     let (tx_audio, rx_audio) = mpsc::channel();
-    let client = EpochClient::new(
+    let mut client = EpochClient::new(
         &address,
         &name,
         matches.value_of("token"),
-        tx_net,
-        rx_net,
+        options,
+        Channels {
+            tx: tx_net,
+            tx_audio: tx_audio.clone(),
+            rx: rx_net,
+        },
         game.clone(),
     );
-    let _handle = thread::spawn(move || client.run(direct));
-    let audio = Audio::new(rx_audio)?;
-    let _audio_handle = thread::spawn(move || audio.run());
+    let network = spawn(move || client.run(direct));
+    let bot = matches.is_present("bot");
+    if bot {
+        let tx_console = tx_gui.clone();
+        // Reading from stdin cannot be interrupted, so this thread is not
+        // joined. It ends at the next line read once the client finished.
+        thread::spawn(move || bot::console(tx_console));
+    }
+    let audio = Audio::new(
+        rx_audio,
+        config.volume,
+        !bot && !matches.is_present("no_audio"),
+        resources.clone(),
+    )?;
+    let audio_handle = spawn(move || audio.run());
+    let tx_quit = tx_gui.clone();
+    let tx_audio_quit = tx_audio.clone();
 
-    let mut g = Gui::new(size, fullscreen, tx_gui, rx_gui, tx_audio, game.clone())?;
-    g.run();
-    Ok(())
+    let gui_ok = if bot {
+        bot::run(&rx_gui);
+        true
+    } else {
+        let options = GuiOptions {
+            size,
+            fullscreen,
+            planning,
+            auto_end,
+            settings: Settings::new(config.volume, config.palette, config.shape_cues),
+            theme,
+        };
+        let mut g = Gui::new(options, tx_gui, rx_gui, tx_audio, game.clone(), &resources)?;
+        let gui_ok = g.run();
+        if g.settings().changed() {
+            config.volume = g.settings().volumes;
+            config.palette = g.settings().palette;
+            config.shape_cues = g.settings().shape_cues;
+            if let Err(e) = config.save(matches.value_of("config")) {
+                warn!("Unable to save settings: {}", e);
+            }
+        }
+        gui_ok
+    };
+
+    debug!("Shutting down.");
+    let _ = tx_quit.send(FromGuiMessage::Quit);
+    let _ = tx_audio_quit.send(AudioMessage::Quit);
+    let network_ok = join("Network", network, Duration::from_secs(SHUTDOWN_TIMEOUT));
+    let audio_ok = join("Audio", audio_handle, Duration::from_secs(SHUTDOWN_TIMEOUT));
+    Ok(match (network_ok, audio_ok) {
+        (None, _) | (_, None) => EXIT_SHUTDOWN,
+        (Some(false), _) => EXIT_NETWORK,
+        _ if !gui_ok => EXIT_ERROR,
+        _ => 0,
+    })
+}
+
+/// A thread signalling on `done` once it finished.
+struct Worker {
+    handle: JoinHandle<bool>,
+    done: Receiver<()>,
+}
+
+/// Runs `f` on a new thread. The sender of `done` is dropped when `f`
+/// panics, so waiting for the thread ends in that case too.
+fn spawn<F: FnOnce() -> bool + Send + 'static>(f: F) -> Worker {
+    let (tx, done) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let result = f();
+        let _ = tx.send(());
+        result
+    });
+    Worker { handle, done }
+}
+
+/// Waits for a thread to finish, giving up after `timeout`. Returns the result
+/// of the thread or `None` if it did not finish in time or panicked.
+fn join(name: &str, worker: Worker, timeout: Duration) -> Option<bool> {
+    if let Err(RecvTimeoutError::Timeout) = worker.done.recv_timeout(timeout) {
+        warn!("{} thread did not finish in time.", name);
+        return None;
+    }
+    match worker.handle.join() {
+        Ok(result) => Some(result),
+        Err(_) => {
+            error!("{} thread panicked.", name);
+            None
+        }
+    }
 }
@@ -5,23 +5,89 @@ pub enum FromGuiMessage {
     Build((u32, u32), Building),
     Excavate((u32, u32)),
     Skip,
+    Chat(String),
     Quit,
 }
 
 #[derive(Debug)]
 pub enum ToGuiMessage {
     Start,
+    Status(String),
     Message(String, String),
+    Chat(String, String),
     ExcavateResult(i32, Option<Building>, (u32, u32)),
     ClearExcavate,
     ClearBuilding,
     UpdateBuildings,
     UpdateGrid,
     SetBuilding((u32, u32), Building),
+    GameOver,
     RequestQuit,
     Quit,
 }
 
-pub enum AudioMessage {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    Master,
+    Music,
+    Effects,
+}
+
+/// A game event with its own sound effect.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Effect {
     Build,
+    Excavate,
+    Found,
+    Error,
+    NewTurn,
+    Warning,
+    Win,
+    Lose,
+    Invalid,
+}
+
+/// Part of the game with its own music.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    Lobby,
+    Game,
+    GameOver,
+}
+
+pub enum AudioMessage {
+    Play(Effect),
+    Phase(Phase),
+    Volume(Channel, f32),
+    Mute(Channel, bool),
+    Quit,
+}
+
+impl Effect {
+    pub const ALL: [Effect; 9] = [
+        Effect::Build,
+        Effect::Excavate,
+        Effect::Found,
+        Effect::Error,
+        Effect::NewTurn,
+        Effect::Warning,
+        Effect::Win,
+        Effect::Lose,
+        Effect::Invalid,
+    ];
+
+    /// Name of the sound file overriding the default sound, without extension.
+    pub fn name(self) -> &'static str {
+        match self {
+            Effect::Build => "build",
+            Effect::Excavate => "excavate",
+            Effect::Found => "found",
+            Effect::Error => "error",
+            Effect::NewTurn => "turn",
+            Effect::Warning => "warning",
+            Effect::Win => "win",
+            Effect::Lose => "lose",
+            Effect::Invalid => "invalid",
+        }
+    }
 }
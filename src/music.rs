@@ -0,0 +1,259 @@
+use failure::Error;
+use rand::seq::SliceRandom;
+use rodio::{Decoder, Source};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::message::Phase;
+use super::resources::{Resource, Resources};
+
+/// Seconds consecutive tracks overlap, also used to fade out on phase changes.
+const CROSSFADE: u32 = 3;
+
+/// A music track fading in at its start and out at its end. The end is found by
+/// decoding one crossfade ahead, so the next track can start while this one
+/// fades out.
+pub struct Track {
+    source: Box<dyn Source<Item = f32> + Send>,
+    channels: u16,
+    sample_rate: u32,
+    /// Decoded samples not yet played.
+    ahead: VecDeque<f32>,
+    /// Length of a fade in samples.
+    fade: usize,
+    played: usize,
+    /// Samples left until a started fade out ends.
+    fading_out: Option<usize>,
+    exhausted: bool,
+    control: TrackControl,
+}
+
+/// Shared between a playing track and the playlist.
+#[derive(Clone, Default)]
+struct TrackControl {
+    /// Set by the playlist to fade out the track early.
+    stop: Arc<AtomicBool>,
+    /// Set by the track once it fades out.
+    ending: Arc<AtomicBool>,
+}
+
+/// Plays the tracks of the current phase in shuffled order.
+pub struct Playlist {
+    resources: Resources,
+    phase: Option<Phase>,
+    tracks: Vec<Resource>,
+    next: usize,
+    current: Option<TrackControl>,
+}
+
+impl Track {
+    pub fn open(resource: &Resource) -> Result<Track, Error> {
+        let source = Decoder::new(Cursor::new(resource.data()?))?.convert_samples();
+        Ok(Track::new(Box::new(source)))
+    }
+
+    fn new(source: Box<dyn Source<Item = f32> + Send>) -> Track {
+        let channels = source.channels();
+        let sample_rate = source.sample_rate();
+        let fade = (CROSSFADE * sample_rate) as usize * channels as usize;
+        let mut track = Track {
+            source,
+            channels,
+            sample_rate,
+            ahead: VecDeque::with_capacity(fade),
+            fade,
+            played: 0,
+            fading_out: None,
+            exhausted: false,
+            control: TrackControl::default(),
+        };
+        // Decoding the first crossfade takes a while, so it is done here on the
+        // audio thread rather than in the first call of the mixer.
+        track.fill();
+        track
+    }
+
+    /// Decodes samples until one crossfade is ahead. Once full, this decodes a
+    /// single sample for each one played.
+    fn fill(&mut self) {
+        while !self.exhausted && self.ahead.len() < self.fade {
+            match self.source.next() {
+                Some(sample) => self.ahead.push_back(sample),
+                None => self.exhausted = true,
+            }
+        }
+    }
+}
+
+impl Iterator for Track {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.fill();
+        if self.fading_out.is_none()
+            && (self.exhausted || self.control.stop.load(Ordering::Relaxed))
+        {
+            self.fading_out = Some(self.ahead.len());
+            self.control.ending.store(true, Ordering::Relaxed);
+        }
+        let sample = self.ahead.pop_front()?;
+        let mut gain = (self.played as f32 / self.fade as f32).min(1.0);
+        self.played += 1;
+        if let Some(left) = self.fading_out {
+            if left == 0 {
+                return None;
+            }
+            gain *= left as f32 / self.fade as f32;
+            self.fading_out = Some(left - 1);
+        }
+        Some(sample * gain)
+    }
+}
+
+impl Source for Track {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Phase {
+    /// Name of the directory in `music/` with the tracks of this phase.
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Lobby => "lobby",
+            Phase::Game => "game",
+            Phase::GameOver => "gameover",
+        }
+    }
+}
+
+impl Playlist {
+    /// Tracks are looked up in `music/<phase>/`, then in `music/`, and
+    /// `bg.ogg` is played if neither contains any.
+    pub fn new(resources: Resources) -> Playlist {
+        Playlist {
+            resources,
+            phase: None,
+            tracks: Vec::new(),
+            next: 0,
+            current: None,
+        }
+    }
+
+    /// Switches to the tracks of `phase`, fading out the current track. Returns
+    /// the first track to play, if the phase changed.
+    pub fn set_phase(&mut self, phase: Phase) -> Option<Track> {
+        if self.phase == Some(phase) {
+            return None;
+        }
+        debug!("Music phase {:?}", phase);
+        self.phase = Some(phase);
+        self.tracks = self.scan(&format!("music/{}", phase.name()));
+        if self.tracks.is_empty() {
+            self.tracks = self.scan("music");
+        }
+        if self.tracks.is_empty() {
+            self.tracks.extend(self.resources.find("bg.ogg"));
+        }
+        self.next = self.tracks.len();
+        if let Some(current) = self.current.take() {
+            current.stop.store(true, Ordering::Relaxed);
+        }
+        self.next_track()
+    }
+
+    /// Returns the next track once the current one starts fading out.
+    pub fn update(&mut self) -> Option<Track> {
+        match self.current {
+            Some(ref current) if current.ending.load(Ordering::Relaxed) => self.next_track(),
+            _ => None,
+        }
+    }
+
+    fn next_track(&mut self) -> Option<Track> {
+        self.current = None;
+        // Every track is tried at most once, so missing files do not loop.
+        for _ in 0..self.tracks.len() {
+            if self.next >= self.tracks.len() {
+                let last = self.tracks.last().cloned();
+                self.tracks.shuffle(&mut rand::rng());
+                // Avoid playing the same track twice in a row.
+                if self.tracks.len() > 1 && self.tracks.first() == last.as_ref() {
+                    self.tracks.swap(0, 1);
+                }
+                self.next = 0;
+            }
+            let resource = &self.tracks[self.next];
+            self.next += 1;
+            match Track::open(resource) {
+                Ok(track) => {
+                    debug!("Playing {}", resource);
+                    self.current = Some(track.control.clone());
+                    return Some(track);
+                }
+                Err(e) => warn!("Unable to play music: {}", e),
+            }
+        }
+        None
+    }
+
+    /// Lists the Ogg Vorbis files in the resource directory `name`.
+    fn scan(&self, name: &str) -> Vec<Resource> {
+        let entries = match self.resources.dir(name).map(fs::read_dir) {
+            Some(Ok(entries)) => entries,
+            _ => return Vec::new(),
+        };
+        let mut tracks = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| e == "ogg"))
+            .collect::<Vec<_>>();
+        tracks.sort();
+        tracks.into_iter().map(Resource::File).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn track(seconds: u32) -> Track {
+        let samples = vec![1.0; (seconds * 100) as usize];
+        Track::new(Box::new(SamplesBuffer::new(1, 100, samples)))
+    }
+
+    #[test]
+    fn open_decodes_one_crossfade_ahead() {
+        let mut track = track(10);
+        assert_eq!(track.ahead.len(), (CROSSFADE * 100) as usize);
+        // Playing tops the buffer up before taking a sample from it.
+        track.next();
+        assert_eq!(track.ahead.len(), (CROSSFADE * 100) as usize - 1);
+        assert!(!track.control.ending.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn short_track_fades_out_at_once() {
+        let mut track = track(1);
+        assert!(track.exhausted);
+        assert_eq!(track.by_ref().count(), 100);
+        assert!(track.control.ending.load(Ordering::Relaxed));
+    }
+}
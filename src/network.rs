@@ -1,35 +1,98 @@
 use failure::Error;
-use serde_json;
 use std::collections::HashMap;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::net::{TcpStream, ToSocketAddrs};
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::runtime;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time;
 
-use super::game::{Building, Game, ScoreEntry};
-use super::message::{FromGuiMessage, ToGuiMessage};
+use super::game::{Building, Connection, Game, ScoreEntry, TurnActions};
+use super::message::{AudioMessage, Effect, FromGuiMessage, Phase, ToGuiMessage};
+use super::transport::{self, ConnectOptions, Transport};
+
+const PROTOCOL_VERSION: u32 = 1;
+const CAPABILITIES: [&str; 5] = ["chat", "spectate", "turn_time", "ping", "leave"];
+/// Seconds between two pings.
+const PING_INTERVAL: u64 = 5;
+/// Seconds without an answer to a ping until the connection is considered lost.
+const PING_TIMEOUT: u64 = 15;
+const RECONNECT_ATTEMPTS: u32 = 5;
+/// Seconds to wait before trying to reconnect.
+const RECONNECT_DELAY: u64 = 2;
+
+/// Channels connecting the network client to the GUI and audio threads.
+pub struct Channels {
+    pub tx: Sender<ToGuiMessage>,
+    pub tx_audio: Sender<AudioMessage>,
+    pub rx: UnboundedReceiver<FromGuiMessage>,
+}
 
 pub struct EpochClient {
     address: String,
     name: String,
     token: Option<String>,
+    options: ConnectOptions,
     game: Arc<Mutex<Game>>,
     tx: Sender<ToGuiMessage>,
-    rx: Receiver<FromGuiMessage>,
+    tx_audio: Sender<AudioMessage>,
+    rx: UnboundedReceiver<FromGuiMessage>,
+}
+
+/// State of a single connection to the game server.
+#[derive(Debug, Default)]
+struct SessionState {
+    /// Whether answers of unknown types or with unknown buildings were reported.
+    reported_unknown: bool,
+    /// Whether malformed answers were reported.
+    reported_malformed: bool,
+    /// Id and send time of the ping waiting for an answer.
+    ping: Option<(u64, Instant)>,
+    /// Action sent last in this turn. The server only answers actions it
+    /// rejects, so an error answer clears the flag of this action.
+    pending: Option<Pending>,
+}
+
+/// An action sent to the server that may still be rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pending {
+    Build,
+    Excavate,
+}
+
+#[derive(Debug, PartialEq)]
+enum SessionEnd {
+    Quit,
+    Lost,
+    /// The server sent its last message, e.g. at the end of the game.
+    Finished,
 }
 
 #[derive(Serialize, Debug)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum Command {
-    Welcome { name: String },
-    Rejoin { token: String },
+    Welcome {
+        name: String,
+        protocol_version: u32,
+        capabilities: Vec<String>,
+    },
+    Spectate {
+        name: String,
+        protocol_version: u32,
+        capabilities: Vec<String>,
+    },
+    Rejoin {
+        token: String,
+        protocol_version: u32,
+        capabilities: Vec<String>,
+    },
     EndTurn,
     Build { x: u32, y: u32, building: Building },
     Excavate { x: u32, y: u32 },
+    Chat { message: String },
+    Ping { id: u64 },
+    Leave,
 }
 
 #[derive(Deserialize, Debug)]
@@ -37,9 +100,14 @@ enum Command {
 #[serde(rename_all = "snake_case")]
 enum Answer {
     Welcome {
-        player: usize,
+        player: Option<usize>,
         map_size: (u32, u32),
+        #[serde(default)]
         rejoin: String,
+        turn_time: Option<u64>,
+        protocol_version: Option<u32>,
+        #[serde(default)]
+        capabilities: Vec<String>,
     },
     EndOfTurn {
         scores: Vec<ScoreEntry>,
@@ -48,6 +116,7 @@ enum Answer {
         excavate_result: Option<ExcavateAnswer>,
         current_prices: HashMap<Building, u32>,
         tower_count: u32,
+        turn_time: Option<u64>,
     },
     Error {
         message: String,
@@ -62,6 +131,22 @@ enum Answer {
     Debug {
         message: String,
     },
+    Chat {
+        name: String,
+        message: String,
+    },
+    Pong {
+        id: u64,
+    },
+    /// An answer of a type this client does not know, kept as it was sent.
+    #[serde(skip)]
+    Unknown {
+        kind: String,
+        raw: serde_json::Value,
+    },
+    /// Any unknown type while deserializing, replaced by `Unknown` in `parse`.
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Deserialize, Debug)]
@@ -77,12 +162,70 @@ struct ExcavateAnswer {
     pos: (u32, u32),
 }
 
+impl Answer {
+    fn parse(s: &str) -> Result<Answer, serde_json::Error> {
+        let raw = serde_json::from_str::<serde_json::Value>(s)?;
+        match serde::Deserialize::deserialize(&raw)? {
+            Answer::Other => {
+                let kind = raw.get("type").and_then(|t| t.as_str()).unwrap_or_default();
+                Ok(Answer::Unknown {
+                    kind: kind.to_string(),
+                    raw,
+                })
+            }
+            answer => Ok(answer),
+        }
+    }
+
+    /// Returns the protocol version of a welcome, even if it does not parse.
+    fn welcome_version(s: &str) -> Option<u32> {
+        let value = serde_json::from_str::<serde_json::Value>(s).ok()?;
+        if value.get("type")?.as_str()? != "welcome" {
+            return None;
+        }
+        Some(value.get("protocol_version")?.as_u64()? as u32)
+    }
+
+    fn unknown_buildings(&self) -> Vec<&str> {
+        let mut buildings = Vec::new();
+        match self {
+            Answer::EndOfTurn {
+                map,
+                excavate_result,
+                current_prices,
+                ..
+            } => {
+                buildings.extend(map.iter().map(|e| &e.building));
+                buildings.extend(excavate_result.iter().filter_map(|e| e.building.as_ref()));
+                buildings.extend(current_prices.keys());
+            }
+            Answer::Error {
+                building: Some(b), ..
+            } => buildings.push(b),
+            _ => {}
+        }
+        let mut names = buildings
+            .into_iter()
+            .filter_map(|b| match b {
+                Building::Unknown(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
 impl Command {
-    pub fn send(&self, stream: &mut TcpStream) -> Result<(), Error> {
+    fn capabilities() -> Vec<String> {
+        CAPABILITIES.iter().map(|c| c.to_string()).collect()
+    }
+
+    pub async fn send(&self, transport: &mut dyn Transport) -> Result<(), Error> {
         let s = serde_json::to_string(self)?;
         trace!("Sending: {}", s);
-        writeln!(stream, "{}", s)?;
-        Ok(())
+        transport.send(&s).await
     }
 }
 
@@ -91,203 +234,942 @@ impl EpochClient {
         address: &str,
         name: &str,
         token: Option<&str>,
-        tx: Sender<ToGuiMessage>,
-        rx: Receiver<FromGuiMessage>,
+        options: ConnectOptions,
+        channels: Channels,
         game: Arc<Mutex<Game>>,
     ) -> EpochClient {
         EpochClient {
             address: address.to_string(),
             name: name.to_string(),
             token: token.map(|x| x.to_string()),
+            options,
             game,
-            tx,
-            rx,
+            tx: channels.tx,
+            tx_audio: channels.tx_audio,
+            rx: channels.rx,
         }
     }
 
-    fn listen(
-        mut reader: BufReader<TcpStream>,
-        tx: Sender<ToGuiMessage>,
-        game: Arc<Mutex<Game>>,
-    ) -> Result<(), Error> {
-        let mut line = String::new();
-        loop {
-            if reader.read_line(&mut line)? == 0 {
-                return Err(format_err!("Connection lost."));
-            }
-            trace!("{}", line.trim());
-            match serde_json::from_str::<Answer>(&line.trim()) {
-                Ok(a) => {
-                    debug!("Answer: {:?}", a);
-                    match a {
-                        Answer::Welcome {
-                            player: p,
-                            map_size: s,
-                            rejoin: r,
-                        } => {
-                            if let Ok(mut g) = game.lock() {
-                                (*g).player = Some(p);
-                                (*g).size = s;
-                                (*g).rejoin = r.clone();
+    fn play(&self, effect: Effect) {
+        let _ = self.tx_audio.send(AudioMessage::Play(effect));
+    }
+
+    /// Switches the music, sent from here so phases arrive in order.
+    fn set_phase(&self, phase: Phase) {
+        let _ = self.tx_audio.send(AudioMessage::Phase(phase));
+    }
+
+    /// Handles a line received from the server. Returns `false` once nothing
+    /// more is expected from the server.
+    fn handle(&self, line: &str, state: &mut SessionState) -> Result<bool, Error> {
+        trace!("{}", line);
+        match Answer::parse(line) {
+            Ok(a) => {
+                debug!("Answer: {:?}", a);
+                let unknown = a.unknown_buildings();
+                if !unknown.is_empty() {
+                    warn!("Unknown building types: {}", unknown.join(", "));
+                    EpochClient::report_unknown(
+                        &self.tx,
+                        &mut state.reported_unknown,
+                        &tr!("unknown-buildings", kinds = unknown.join(", ")),
+                    )?;
+                }
+                match a {
+                    Answer::Welcome {
+                        player: p,
+                        map_size: s,
+                        rejoin: r,
+                        turn_time: t,
+                        protocol_version: v,
+                        capabilities: c,
+                    } => {
+                        if let Some(v) = v {
+                            if v != PROTOCOL_VERSION {
+                                EpochClient::incompatible(&self.tx, Some(v))?;
+                                return Ok(false);
                             }
-                            tx.send(ToGuiMessage::UpdateGrid)?;
-                            tx.send(ToGuiMessage::Start)?;
                         }
-                        Answer::EndOfTurn {
-                            scores,
-                            map,
-                            turn,
-                            excavate_result,
-                            current_prices,
-                            tower_count,
-                        } => {
-                            tx.send(ToGuiMessage::UpdateGrid)?;
-                            tx.send(ToGuiMessage::UpdateBuildings)?;
-                            tx.send(ToGuiMessage::ClearBuilding)?;
-                            tx.send(ToGuiMessage::ClearExcavate)?;
-                            if let Ok(mut g) = game.lock() {
-                                (*g).scores = scores;
-                                (*g).buildings.clear();
-                                (*g).turn = turn;
-                                (*g).prices = current_prices;
-                                (*g).tower_count = tower_count;
-                                for e in map {
-                                    (*g).buildings.insert(e.pos, e.building);
-                                }
-                                if let Some(er) = excavate_result {
-                                    tx.send(ToGuiMessage::ExcavateResult(
-                                        er.depth,
-                                        er.building,
-                                        er.pos,
-                                    ))?;
-                                }
+                        if let Ok(mut g) = self.game.lock() {
+                            g.capabilities = c;
+                            g.player = p;
+                            g.size = s;
+                            if !r.is_empty() {
+                                g.rejoin = r;
                             }
+                            g.start_turn(t);
+                            g.latency = None;
+                            g.connection = Connection::Unknown;
                         }
-                        Answer::GameOver { message, score } => {
-                            tx.send(ToGuiMessage::Message(
-                                "Finish".to_string(),
-                                format!("{}\nScore: {}", message, score),
-                            ))?;
-                            tx.send(ToGuiMessage::RequestQuit)?;
+                        self.tx.send(ToGuiMessage::UpdateGrid)?;
+                        self.tx.send(ToGuiMessage::Start)?;
+                        self.set_phase(Phase::Game);
+                    }
+                    Answer::EndOfTurn {
+                        scores,
+                        map,
+                        turn,
+                        excavate_result,
+                        current_prices,
+                        tower_count,
+                        turn_time,
+                    } => {
+                        state.pending = None;
+                        self.tx.send(ToGuiMessage::UpdateGrid)?;
+                        self.tx.send(ToGuiMessage::UpdateBuildings)?;
+                        self.tx.send(ToGuiMessage::ClearBuilding)?;
+                        self.tx.send(ToGuiMessage::ClearExcavate)?;
+                        self.play(Effect::NewTurn);
+                        if let Ok(mut g) = self.game.lock() {
+                            // Builds rejected by the server have been reset already.
+                            if g.actions.build {
+                                self.play(Effect::Build);
+                            }
+                            g.scores = scores;
+                            g.buildings.clear();
+                            g.turn = turn;
+                            g.prices = current_prices;
+                            g.tower_count = tower_count;
+                            g.start_turn(turn_time);
+                            for e in map {
+                                g.buildings.insert(e.pos, e.building);
+                            }
+                            if let Some(er) = excavate_result {
+                                if er.building.is_some() {
+                                    self.play(Effect::Found);
+                                }
+                                self.tx.send(ToGuiMessage::ExcavateResult(
+                                    er.depth,
+                                    er.building,
+                                    er.pos,
+                                ))?;
+                            }
                         }
-                        Answer::Debug { message: msg } => {
-                            info!("Debug message from server: \n{}", msg)
+                    }
+                    Answer::GameOver { message, score } => {
+                        let won = match self.game.lock() {
+                            Ok(g) => {
+                                g.spectator || g.scores.iter().all(|s| s.score <= score as isize)
+                            }
+                            Err(_) => false,
+                        };
+                        self.play(if won { Effect::Win } else { Effect::Lose });
+                        self.set_phase(Phase::GameOver);
+                        self.tx.send(ToGuiMessage::GameOver)?;
+                        self.tx.send(ToGuiMessage::Message(
+                            tr!("game-over"),
+                            format!("{}\n{}", message, tr!("final-score", score = score)),
+                        ))?;
+                        self.tx.send(ToGuiMessage::RequestQuit)?;
+                        return Ok(false);
+                    }
+                    Answer::Debug { message: msg } => {
+                        info!("Debug message from server: \n{}", msg)
+                    }
+                    Answer::Chat { name, message } => {
+                        info!("{}: {}", name, message);
+                        self.tx.send(ToGuiMessage::Chat(name, message))?;
+                    }
+                    Answer::Pong { id } => {
+                        match state.ping {
+                            Some((ping, sent)) if ping == id => {
+                                let latency = sent.elapsed();
+                                trace!("Latency: {:?}", latency);
+                                state.ping = None;
+                                lock(&self.game)?.set_latency(latency);
+                            }
+                            _ => debug!("Ignoring unexpected pong {}", id),
                         }
-                        Answer::Error {
-                            message: msg,
-                            subtype: st,
-                            pos: p,
-                            building: b,
-                        } => {
-                            info!("Error message from server: \n{}", msg);
-                            tx.send(ToGuiMessage::Message("Error".to_string(), msg))?;
-                            if let Some(subtype) = st {
-                                match subtype.to_lowercase().as_str() {
-                                    "invalidbuilderror" => tx.send(ToGuiMessage::ClearBuilding)?,
-                                    "buildactionalreadyusederror" => {
-                                        tx.send(ToGuiMessage::ClearBuilding)?;
-                                        if let Some(pos) = p {
-                                            if let Some(building) = b {
-                                                tx.send(ToGuiMessage::SetBuilding(
-                                                    pos,
-                                                    building.clone(),
-                                                ))?;
-                                            }
-                                        }
-                                    }
-                                    "gamealreadyrunning" => {
-                                        tx.send(ToGuiMessage::Quit)?;
-                                        return Ok(());
+                    }
+                    Answer::Unknown { kind, raw } => {
+                        warn!("Unknown answer type {}: {}", kind, raw);
+                        EpochClient::report_unknown(
+                            &self.tx,
+                            &mut state.reported_unknown,
+                            &tr!("unknown-messages", kind = kind.as_str()),
+                        )?;
+                    }
+                    Answer::Other => {}
+                    Answer::Error {
+                        message: msg,
+                        subtype: st,
+                        pos: p,
+                        building: b,
+                    } => {
+                        info!("Error message from server: \n{}", msg);
+                        self.play(Effect::Error);
+                        self.tx.send(ToGuiMessage::Message(tr!("error"), msg))?;
+                        let subtype = st.map(|s| s.to_lowercase()).unwrap_or_default();
+                        // The subtypes handled here are the ones of the original
+                        // client; any other error is taken to reject the action
+                        // sent last.
+                        let rejected = match subtype.as_str() {
+                            "invalidbuilderror" => {
+                                state.pending = None;
+                                Some(Pending::Build)
+                            }
+                            "buildactionalreadyusederror" => {
+                                state.pending = None;
+                                if let Ok(mut g) = self.game.lock() {
+                                    g.actions.build = true;
+                                }
+                                self.tx.send(ToGuiMessage::ClearBuilding)?;
+                                if let Some(pos) = p {
+                                    if let Some(building) = b {
+                                        self.tx.send(ToGuiMessage::SetBuilding(
+                                            pos,
+                                            building.clone(),
+                                        ))?;
                                     }
-                                    s => trace!("Got error subtype {}", s),
                                 }
+                                None
+                            }
+                            "incompatibleprotocol" => {
+                                EpochClient::incompatible(&self.tx, None)?;
+                                return Ok(false);
+                            }
+                            "gamealreadyrunning" => {
+                                self.tx.send(ToGuiMessage::Quit)?;
+                                return Ok(false);
+                            }
+                            s => {
+                                if !s.is_empty() {
+                                    trace!("Got error subtype {}", s);
+                                }
+                                state.pending.take()
+                            }
+                        };
+                        match rejected {
+                            Some(Pending::Build) => {
+                                self.use_action(|a| a.build = false)?;
+                                self.tx.send(ToGuiMessage::ClearBuilding)?;
+                            }
+                            Some(Pending::Excavate) => {
+                                self.use_action(|a| a.excavate = false)?;
+                                self.tx.send(ToGuiMessage::ClearExcavate)?;
                             }
+                            None => {}
                         }
                     }
                 }
-                Err(e) => {
-                    trace!("{:?}", e);
-                    warn!("{}", e)
+            }
+            Err(e) => {
+                trace!("{:?}", e);
+                warn!("{}", e);
+                // A welcome of another protocol version may not parse at all.
+                if let Some(v) = Answer::welcome_version(line) {
+                    if v != PROTOCOL_VERSION {
+                        EpochClient::incompatible(&self.tx, Some(v))?;
+                        return Ok(false);
+                    }
                 }
+                EpochClient::report_unknown(
+                    &self.tx,
+                    &mut state.reported_malformed,
+                    &tr!("malformed-messages"),
+                )?;
             }
-            line.clear()
         }
+        Ok(true)
+    }
+
+    fn incompatible(tx: &Sender<ToGuiMessage>, version: Option<u32>) -> Result<(), Error> {
+        let hint = match version {
+            Some(v) if v < PROTOCOL_VERSION => tr!("use-older-client"),
+            _ => tr!("upgrade-client"),
+        };
+        error!("Incompatible server protocol {:?}.", version);
+        let version = match version {
+            Some(v) => tr!("protocol-version", version = v),
+            None => tr!("protocol-unknown"),
+        };
+        tx.send(ToGuiMessage::Message(
+            tr!("incompatible-server"),
+            format!(
+                "{}\n{}",
+                tr!("incompatible-protocol", version = version, supported = PROTOCOL_VERSION),
+                hint
+            ),
+        ))?;
+        tx.send(ToGuiMessage::RequestQuit)?;
+        Ok(())
+    }
+
+    fn report_unknown(
+        tx: &Sender<ToGuiMessage>,
+        reported: &mut bool,
+        what: &str,
+    ) -> Result<(), Error> {
+        if !*reported {
+            *reported = true;
+            tx.send(ToGuiMessage::Message(
+                tr!("warning"),
+                tr!("unknown-data", data = what),
+            ))?;
+        }
+        Ok(())
     }
 
-    pub fn run(&self, direct: bool) {
-        if let Err(err) = self.run_res(direct) {
-            for e in err.iter_chain() {
-                error!("{}", e);
+    /// Tells the server that the player leaves and closes the connection.
+    async fn leave(&self, transport: &mut dyn Transport) {
+        let leave = match self.game.lock() {
+            Ok(game) => game.capabilities.iter().any(|c| c == "leave"),
+            Err(_) => false,
+        };
+        if leave {
+            if let Err(e) = Command::Leave.send(transport).await {
+                debug!("Unable to send leave: {}", e);
             }
         }
+        if let Err(e) = transport.close().await {
+            debug!("Unable to close the connection: {}", e);
+        }
+        debug!("Left the game.");
+    }
+
+    fn use_action<F: FnOnce(&mut TurnActions)>(&self, f: F) -> Result<(), Error> {
+        f(&mut lock(&self.game)?.actions);
+        Ok(())
+    }
+
+    /// Runs the client on its own runtime, blocking until it finished. Returns
+    /// whether the client finished without an error.
+    pub fn run(&mut self, direct: bool) -> bool {
+        let result = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(Error::from)
+            .and_then(|rt| rt.block_on(self.run_async(direct)));
         debug!("Network thread finished.");
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                for e in err.iter_chain() {
+                    error!("{}", e);
+                }
+                let _ = self.tx.send(ToGuiMessage::Status(tr!(transport::error_id(&err))));
+                false
+            }
+        }
+    }
+
+    /// Connects to the server and forwards messages until the player quits,
+    /// reconnecting when the connection is lost.
+    pub async fn run_async(&mut self, direct: bool) -> Result<(), Error> {
+        let mut server = None;
+        let mut reconnect = false;
+        let mut attempts = 0;
+        loop {
+            let end = self.session(direct, &mut server, reconnect).await;
+            // After the game is over its music keeps playing.
+            if let Ok(SessionEnd::Quit) | Ok(SessionEnd::Lost) = end {
+                self.set_phase(Phase::Lobby);
+            }
+            match end {
+                Ok(SessionEnd::Quit) | Ok(SessionEnd::Finished) => return Ok(()),
+                Ok(SessionEnd::Lost) => attempts = 0,
+                Err(e) => {
+                    if !reconnect {
+                        return Err(e);
+                    }
+                    warn!("Reconnecting failed: {}", e);
+                    if attempts >= RECONNECT_ATTEMPTS {
+                        self.tx.send(ToGuiMessage::Message(
+                            tr!("connection-lost-title"),
+                            tr!("reconnect-failed", error = tr!(transport::error_id(&e))),
+                        ))?;
+                        self.tx.send(ToGuiMessage::RequestQuit)?;
+                        return Err(e);
+                    }
+                }
+            }
+            {
+                let mut game = lock(&self.game)?;
+                if !game.spectator && game.rejoin.is_empty() {
+                    return Err(format_err!("Connection lost."));
+                }
+                game.latency = None;
+                game.connection = Connection::Lost;
+            }
+            reconnect = true;
+            attempts += 1;
+            let _ = self.tx.send(ToGuiMessage::Status(tr!(
+                "reconnect-attempt",
+                attempt = attempts,
+                attempts = RECONNECT_ATTEMPTS
+            )));
+            let delay = time::sleep(Duration::from_secs(RECONNECT_DELAY));
+            tokio::pin!(delay);
+            loop {
+                tokio::select! {
+                    _ = &mut delay => break,
+                    msg = self.rx.recv() => match msg {
+                        Some(FromGuiMessage::Quit) | None => return Ok(()),
+                        Some(msg) => debug!("Dropping {:?} while reconnecting.", msg),
+                    },
+                }
+            }
+        }
     }
 
-    fn run_res(&self, direct: bool) -> Result<(), Error> {
+    /// Connects to the game server, asking the session server for its address
+    /// unless connecting directly or the address is already known.
+    async fn connect(
+        address: &str,
+        options: &ConnectOptions,
+        direct: bool,
+        server: &mut Option<String>,
+        report: &(dyn Fn(String) + Sync),
+    ) -> Result<Box<dyn Transport>, Error> {
+        if let Some(ref address) = server {
+            return transport::connect(address, options, report).await;
+        }
+        if direct {
+            return transport::connect(address, options, report).await;
+        }
+        let line = transport::connect(address, options, report)
+            .await?
+            .receive()
+            .await?
+            .ok_or(format_err!("Session server closed the connection."))?;
+        debug!("Using server {}", line);
+        let game_server = transport::resolve(&line, address);
+        let transport = transport::connect(&game_server, options, report).await?;
+        *server = Some(game_server);
+        Ok(transport)
+    }
+
+    /// Connects to the game server and handles messages from the server and
+    /// the GUI until the player quits or the connection is lost. When
+    /// reconnecting, the session is resumed with the rejoin token from the
+    /// server.
+    async fn session(
+        &mut self,
+        direct: bool,
+        server: &mut Option<String>,
+        reconnect: bool,
+    ) -> Result<SessionEnd, Error> {
         debug!("Connecting to address: {}", self.address);
-        let mut stream = {
-            if direct {
-                TcpStream::connect_timeout(
-                    &self
-                        .address
-                        .to_socket_addrs()?
-                        .next()
-                        .ok_or(format_err!("Error while parsing address."))?,
-                    Duration::from_millis(20000),
-                )?
-            } else {
-                let tmp = TcpStream::connect_timeout(
-                    &self
-                        .address
-                        .to_socket_addrs()?
-                        .next()
-                        .ok_or(format_err!("Error while parsing address."))?,
-                    Duration::from_millis(20000),
-                )?;
-                let mut line = String::new();
-                let mut reader = BufReader::new(tmp);
-                reader.read_line(&mut line)?;
-                debug!("Using server {}", line.trim());
-                TcpStream::connect_timeout(
-                    &line
-                        .trim()
-                        .to_socket_addrs()?
-                        .next()
-                        .ok_or(format_err!("Error while parsing address."))?,
-                    Duration::from_millis(20000),
-                )?
+        if !reconnect {
+            self.set_phase(Phase::Lobby);
+        }
+        let tx = self.tx.clone();
+        let report = move |status: String| {
+            info!("{}", status);
+            let _ = tx.send(ToGuiMessage::Status(status));
+        };
+        let mut transport = {
+            let connecting =
+                EpochClient::connect(&self.address, &self.options, direct, server, &report);
+            tokio::pin!(connecting);
+            loop {
+                tokio::select! {
+                    transport = &mut connecting => break transport?,
+                    msg = self.rx.recv() => match msg {
+                        Some(FromGuiMessage::Quit) | None => return Ok(SessionEnd::Quit),
+                        Some(msg) => debug!("Dropping {:?} while connecting.", msg),
+                    },
+                }
             }
         };
-        stream.set_write_timeout(Some(Duration::from_millis(1000)))?;
         debug!("Connected.");
-        let reader = BufReader::new(stream.try_clone()?);
-        let _ = {
-            let game = self.game.clone();
-            let tx = self.tx.clone();
-            thread::spawn(move || EpochClient::listen(reader, tx, game))
+        self.serve(transport.as_mut(), reconnect).await
+    }
+
+    /// Joins the game over an established connection and handles messages
+    /// until the player quits or the connection is lost.
+    async fn serve(
+        &mut self,
+        transport: &mut dyn Transport,
+        reconnect: bool,
+    ) -> Result<SessionEnd, Error> {
+        let (spectator, rejoin) = {
+            let game = lock(&self.game)?;
+            (game.spectator, game.rejoin.clone())
         };
-        if let Some(ref t) = self.token {
-            Command::Rejoin { token: t.clone() }.send(&mut stream)?;
+        let token = if reconnect {
+            Some(rejoin)
+        } else {
+            self.token.clone()
+        };
+        if spectator {
+            Command::Spectate {
+                name: self.name.clone(),
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: Command::capabilities(),
+            }.send(transport)
+                .await?;
+        } else if let Some(t) = token {
+            Command::Rejoin {
+                token: t,
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: Command::capabilities(),
+            }.send(transport)
+                .await?;
         } else {
             Command::Welcome {
                 name: self.name.clone(),
-            }.send(&mut stream)?;
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: Command::capabilities(),
+            }.send(transport)
+                .await?;
         }
-        while let Ok(msg) = self.rx.recv() {
-            trace!("Got message from GUI: {:?}", msg);
-            match msg {
-                FromGuiMessage::Build(pos, building) => Command::Build {
-                    x: pos.0,
-                    y: pos.1,
-                    building,
-                }.send(&mut stream)?,
-                FromGuiMessage::Excavate(pos) => {
-                    Command::Excavate { x: pos.0, y: pos.1 }.send(&mut stream)?
+        let mut state = SessionState::default();
+        let mut next_ping = 0;
+        let mut ticks = time::interval(Duration::from_secs(PING_INTERVAL));
+        loop {
+            tokio::select! {
+                line = transport.receive() => match line {
+                    Ok(Some(line)) => {
+                        if !self.handle(&line, &mut state)? {
+                            if let Err(e) = transport.close().await {
+                                debug!("Unable to close the connection: {}", e);
+                            }
+                            return Ok(SessionEnd::Finished);
+                        }
+                    }
+                    Ok(None) => {
+                        warn!("Connection closed by the server.");
+                        return Ok(SessionEnd::Lost);
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        return Ok(SessionEnd::Lost);
+                    }
+                },
+                msg = self.rx.recv() => {
+                    trace!("Got message from GUI: {:?}", msg);
+                    let action = matches!(
+                        msg,
+                        Some(FromGuiMessage::Build(..))
+                            | Some(FromGuiMessage::Excavate(_))
+                            | Some(FromGuiMessage::Skip)
+                    );
+                    if spectator && action {
+                        debug!("Ignoring action while spectating.");
+                        continue;
+                    }
+                    let command = match msg {
+                        Some(FromGuiMessage::Build(pos, building)) => {
+                            self.use_action(|a| a.build = true)?;
+                            state.pending = Some(Pending::Build);
+                            Command::Build {
+                                x: pos.0,
+                                y: pos.1,
+                                building,
+                            }
+                        }
+                        Some(FromGuiMessage::Excavate(pos)) => {
+                            self.use_action(|a| a.excavate = true)?;
+                            state.pending = Some(Pending::Excavate);
+                            self.play(Effect::Excavate);
+                            Command::Excavate { x: pos.0, y: pos.1 }
+                        }
+                        Some(FromGuiMessage::Skip) => {
+                            self.use_action(|a| a.end_turn = true)?;
+                            Command::EndTurn
+                        }
+                        Some(FromGuiMessage::Chat(message)) => Command::Chat { message },
+                        Some(FromGuiMessage::Quit) | None => {
+                            self.leave(transport).await;
+                            return Ok(SessionEnd::Quit);
+                        }
+                    };
+                    if let Err(e) = command.send(transport).await {
+                        warn!("Sending failed: {}", e);
+                        return Ok(SessionEnd::Lost);
+                    }
+                }
+                _ = ticks.tick() => {
+                    if let Some((_, sent)) = state.ping {
+                        if sent.elapsed() > Duration::from_secs(PING_TIMEOUT) {
+                            warn!("Server did not answer the ping, connection lost.");
+                            return Ok(SessionEnd::Lost);
+                        }
+                    } else if lock(&self.game)?.capabilities.iter().any(|c| c == "ping") {
+                        next_ping += 1;
+                        state.ping = Some((next_ping, Instant::now()));
+                        if (Command::Ping { id: next_ping }).send(transport).await.is_err() {
+                            return Ok(SessionEnd::Lost);
+                        }
+                    }
                 }
-                FromGuiMessage::Skip => Command::EndTurn.send(&mut stream)?,
-                FromGuiMessage::Quit => break,
             }
         }
-        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, Error> {
+    mutex
+        .lock()
+        .map_err(|_| format_err!("Error while locking Mutex."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self as std_mpsc, Receiver};
+    use tokio::sync::mpsc::{self, UnboundedSender};
+    use transport::MemoryTransport;
+
+    const WELCOME: &str = r#"{"type": "welcome", "player": 0, "map_size": [8, 8],
+        "rejoin": "token", "protocol_version": 1, "capabilities": ["chat", "leave"]}"#;
+
+    struct Harness {
+        client: EpochClient,
+        game: Arc<Mutex<Game>>,
+        gui: Receiver<ToGuiMessage>,
+        tx_gui: UnboundedSender<FromGuiMessage>,
+        audio: Receiver<AudioMessage>,
+    }
+
+    fn harness() -> Harness {
+        let (tx, gui) = std_mpsc::channel();
+        let (tx_audio, audio) = std_mpsc::channel();
+        let (tx_gui, rx) = mpsc::unbounded_channel();
+        let game = Arc::new(Mutex::new(Game::new()));
+        let channels = Channels { tx, tx_audio, rx };
+        let client = EpochClient::new(
+            "localhost:4200",
+            "tester",
+            None,
+            ConnectOptions::default(),
+            channels,
+            game.clone(),
+        );
+        Harness {
+            client,
+            game,
+            gui,
+            tx_gui,
+            audio,
+        }
+    }
+
+    /// Waits for the next message to the GUI without blocking the runtime.
+    async fn next_gui(gui: &Receiver<ToGuiMessage>) -> ToGuiMessage {
+        loop {
+            if let Ok(msg) = gui.try_recv() {
+                return msg;
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    async fn expect(server: &mut MemoryTransport, kind: &str) -> serde_json::Value {
+        let line = server.receive().await.unwrap().expect("connection closed");
+        let value = serde_json::from_str::<serde_json::Value>(&line).unwrap();
+        assert_eq!(value["type"], kind, "unexpected command {}", line);
+        value
+    }
+
+    #[tokio::test]
+    async fn session_welcome_chat_and_leave() {
+        let Harness {
+            mut client,
+            game,
+            gui,
+            tx_gui,
+            ..
+        } = harness();
+        let (mut transport, mut server) = MemoryTransport::pair();
+        let script = async {
+            let welcome = expect(&mut server, "welcome").await;
+            assert_eq!(welcome["name"], "tester");
+            assert_eq!(welcome["protocol_version"], PROTOCOL_VERSION);
+            server.send(WELCOME).await.unwrap();
+            while !matches!(next_gui(&gui).await, ToGuiMessage::Start) {}
+            assert_eq!(lock(&game).unwrap().rejoin, "token");
+            tx_gui.send(FromGuiMessage::Chat("hello".to_string())).unwrap();
+            assert_eq!(expect(&mut server, "chat").await["message"], "hello");
+            tx_gui.send(FromGuiMessage::Quit).unwrap();
+            expect(&mut server, "leave").await;
+        };
+        let (end, ()) = tokio::join!(client.serve(&mut transport, false), script);
+        assert_eq!(end.unwrap(), SessionEnd::Quit);
+    }
+
+    #[tokio::test]
+    async fn session_spectator_sends_no_actions() {
+        let Harness {
+            mut client,
+            game,
+            tx_gui,
+            ..
+        } = harness();
+        game.lock().unwrap().spectator = true;
+        let (mut transport, mut server) = MemoryTransport::pair();
+        let script = async {
+            expect(&mut server, "spectate").await;
+            tx_gui.send(FromGuiMessage::Skip).unwrap();
+            tx_gui.send(FromGuiMessage::Chat("hi".to_string())).unwrap();
+            expect(&mut server, "chat").await;
+            drop(server);
+        };
+        let (end, ()) = tokio::join!(client.serve(&mut transport, false), script);
+        assert_eq!(end.unwrap(), SessionEnd::Lost);
+    }
+
+    #[tokio::test]
+    async fn session_rejoins_after_reconnect() {
+        let Harness {
+            mut client,
+            game,
+            tx_gui: _tx_gui,
+            ..
+        } = harness();
+        game.lock().unwrap().rejoin = "secret".to_string();
+        let (mut transport, mut server) = MemoryTransport::pair();
+        let script = async {
+            assert_eq!(expect(&mut server, "rejoin").await["token"], "secret");
+            drop(server);
+        };
+        let (end, ()) = tokio::join!(client.serve(&mut transport, true), script);
+        assert_eq!(end.unwrap(), SessionEnd::Lost);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn session_finishes_after_game_over() {
+        let Harness {
+            mut client,
+            gui: _gui,
+            tx_gui: _tx_gui,
+            ..
+        } = harness();
+        let (mut transport, mut server) = MemoryTransport::pair();
+        let script = async {
+            expect(&mut server, "welcome").await;
+            server.send(&WELCOME.replace("\"leave\"", "\"ping\"")).await.unwrap();
+            server
+                .send(r#"{"type": "game_over", "message": "Game over", "score": 3}"#)
+                .await
+                .unwrap();
+            // The server stays silent, pings must neither be sent nor time out.
+            time::sleep(Duration::from_secs(10 * PING_TIMEOUT)).await;
+            server
+        };
+        let (end, mut server) = tokio::join!(client.serve(&mut transport, false), script);
+        assert_eq!(end.unwrap(), SessionEnd::Finished);
+        drop(transport);
+        assert_eq!(server.receive().await.unwrap(), None);
+    }
+
+    #[test]
+    fn excavate_error_clears_action() {
+        let h = harness();
+        h.game.lock().unwrap().actions.excavate = true;
+        let line = r#"{"type": "error", "message": "No"}"#;
+        let mut state = SessionState {
+            pending: Some(Pending::Excavate),
+            ..SessionState::default()
+        };
+        assert!(h.client.handle(line, &mut state).unwrap());
+        assert!(!h.game.lock().unwrap().actions.excavate);
+        assert_eq!(state.pending, None);
+        assert!(h.gui.try_iter().any(|m| matches!(m, ToGuiMessage::ClearExcavate)));
+        // Without a pending action, errors leave the actions alone.
+        h.game.lock().unwrap().actions.build = true;
+        assert!(h.client.handle(line, &mut state).unwrap());
+        assert!(h.game.lock().unwrap().actions.build);
+    }
+
+    #[tokio::test]
+    async fn music_phases_of_a_game() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        use tokio::net::TcpListener;
+
+        let Harness {
+            mut client,
+            gui: _gui,
+            tx_gui: _tx_gui,
+            audio,
+            ..
+        } = harness();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        client.address = listener.local_addr().unwrap().to_string();
+        let script = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            assert!(line.contains("welcome"), "unexpected command {}", line);
+            let answers = format!(
+                "{}\n{}\n",
+                WELCOME.replace('\n', ""),
+                r#"{"type": "game_over", "message": "Game over", "score": 3}"#
+            );
+            stream.get_mut().write_all(answers.as_bytes()).await.unwrap();
+            stream
+        };
+        let (result, _stream) = tokio::join!(client.run_async(true), script);
+        result.unwrap();
+        let phases: Vec<_> = audio
+            .try_iter()
+            .filter_map(|m| match m {
+                AudioMessage::Phase(phase) => Some(phase),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(phases, vec![Phase::Lobby, Phase::Game, Phase::GameOver]);
+    }
+
+    #[tokio::test]
+    async fn lobby_music_after_leaving() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        use tokio::net::TcpListener;
+
+        let Harness {
+            mut client,
+            gui,
+            tx_gui,
+            audio,
+            ..
+        } = harness();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        client.address = listener.local_addr().unwrap().to_string();
+        let script = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let welcome = format!("{}\n", WELCOME.replace('\n', ""));
+            stream.get_mut().write_all(welcome.as_bytes()).await.unwrap();
+            while !matches!(next_gui(&gui).await, ToGuiMessage::Start) {}
+            tx_gui.send(FromGuiMessage::Quit).unwrap();
+            stream
+        };
+        let (result, _stream) = tokio::join!(client.run_async(true), script);
+        result.unwrap();
+        let phases: Vec<_> = audio
+            .try_iter()
+            .filter_map(|m| match m {
+                AudioMessage::Phase(phase) => Some(phase),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(phases, vec![Phase::Lobby, Phase::Game, Phase::Lobby]);
+    }
+
+    #[test]
+    fn parse_welcome() {
+        let answer = Answer::parse(
+            r#"{"type": "welcome", "player": 1, "map_size": [10, 12], "rejoin": "abc",
+                "protocol_version": 1, "capabilities": ["ping"]}"#,
+        ).unwrap();
+        match answer {
+            Answer::Welcome {
+                player,
+                map_size,
+                rejoin,
+                turn_time,
+                protocol_version,
+                capabilities,
+            } => {
+                assert_eq!(player, Some(1));
+                assert_eq!(map_size, (10, 12));
+                assert_eq!(rejoin, "abc");
+                assert_eq!(turn_time, None);
+                assert_eq!(protocol_version, Some(1));
+                assert_eq!(capabilities, vec!["ping".to_string()]);
+            }
+            a => panic!("Parsed as {:?}", a),
+        }
+    }
+
+    #[test]
+    fn parse_end_of_turn() {
+        let answer = Answer::parse(
+            r#"{"type": "end_of_turn", "scores": [{"name": "a", "score": 3}],
+                "map": [{"pos": [1, 2], "building": "house"}], "turn": 4,
+                "excavate_result": {"depth": 2, "building": null, "pos": [3, 4]},
+                "current_prices": {"villa": 5, "castle": 9}, "tower_count": 1}"#,
+        ).unwrap();
+        match &answer {
+            Answer::EndOfTurn {
+                scores,
+                map,
+                turn,
+                excavate_result,
+                current_prices,
+                ..
+            } => {
+                assert_eq!(scores[0].score, 3);
+                assert_eq!(map[0].building, Building::House);
+                assert_eq!(*turn, 4);
+                assert_eq!(excavate_result.as_ref().map(|e| e.pos), Some((3, 4)));
+                assert_eq!(current_prices.get(&Building::Villa), Some(&5));
+            }
+            a => panic!("Parsed as {:?}", a),
+        }
+        assert_eq!(answer.unknown_buildings(), vec!["castle"]);
+    }
+
+    #[test]
+    fn parse_error() {
+        let answer = Answer::parse(
+            r#"{"type": "error", "message": "No", "subtype": "InvalidBuildError",
+                "pos": [1, 1], "building": "tower"}"#,
+        ).unwrap();
+        match answer {
+            Answer::Error {
+                subtype, building, ..
+            } => {
+                assert_eq!(subtype.as_deref(), Some("InvalidBuildError"));
+                assert_eq!(building, Some(Building::Tower));
+            }
+            a => panic!("Parsed as {:?}", a),
+        }
+    }
+
+    #[test]
+    fn parse_simple_answers() {
+        match Answer::parse(r#"{"type": "game_over", "message": "Bye", "score": -2}"#) {
+            Ok(Answer::GameOver { score: -2, .. }) => {}
+            a => panic!("Parsed as {:?}", a),
+        }
+        match Answer::parse(r#"{"type": "debug", "message": "x"}"#) {
+            Ok(Answer::Debug { .. }) => {}
+            a => panic!("Parsed as {:?}", a),
+        }
+        match Answer::parse(r#"{"type": "chat", "name": "a", "message": "hi"}"#) {
+            Ok(Answer::Chat { name, message }) => {
+                assert_eq!(name, "a");
+                assert_eq!(message, "hi");
+            }
+            a => panic!("Parsed as {:?}", a),
+        }
+        match Answer::parse(r#"{"type": "pong", "id": 7}"#) {
+            Ok(Answer::Pong { id: 7 }) => {}
+            a => panic!("Parsed as {:?}", a),
+        }
+    }
+
+    #[test]
+    fn parse_unknown_type() {
+        let line = r#"{"type": "trade", "offer": 3}"#;
+        match Answer::parse(line) {
+            Ok(Answer::Unknown { kind, raw }) => {
+                assert_eq!(kind, "trade");
+                assert_eq!(raw["offer"], 3);
+            }
+            a => panic!("Parsed as {:?}", a),
+        }
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert!(Answer::parse("{\"type\": \"pong\"").is_err());
+        assert!(Answer::parse("not json").is_err());
+        assert!(Answer::parse(r#"{"id": 1}"#).is_err());
+        // Known types with missing fields are not mistaken for unknown ones.
+        assert!(Answer::parse(r#"{"type": "pong"}"#).is_err());
+    }
+
+    #[test]
+    fn welcome_version_of_unparsable_welcome() {
+        let welcome = r#"{"type": "welcome", "protocol_version": 2}"#;
+        assert_eq!(Answer::welcome_version(welcome), Some(2));
+        let pong = r#"{"type": "pong", "protocol_version": 2}"#;
+        assert_eq!(Answer::welcome_version(pong), None);
     }
 }
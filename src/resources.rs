@@ -0,0 +1,183 @@
+use failure::Error;
+use sdl2::rwops::RWops;
+use std::borrow::Cow;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Resources compiled into the executable, used if no file overrides them.
+/// These are the files shipped in `res/`. Sound effects in `sounds/` and music
+/// in `music/` are only read from the resource directories; without them the
+/// embedded `build.ogg` and `bg.ogg` or generated tones are played.
+#[cfg(feature = "embed-assets")]
+const EMBEDDED: &[(&str, &[u8])] = &[
+    ("bg.ogg", include_bytes!("../res/bg.ogg")),
+    ("bg.png", include_bytes!("../res/bg.png")),
+    ("build.ogg", include_bytes!("../res/build.ogg")),
+    ("ex.png", include_bytes!("../res/ex.png")),
+    ("font.ttf", include_bytes!("../res/font.ttf")),
+    ("house.png", include_bytes!("../res/house.png")),
+    ("locales/de.ftl", include_bytes!("../res/locales/de.ftl")),
+    ("locales/en.ftl", include_bytes!("../res/locales/en.ftl")),
+    ("skip.png", include_bytes!("../res/skip.png")),
+    ("themes/default.json", include_bytes!("../res/themes/default.json")),
+    ("themes/high-contrast.json", include_bytes!("../res/themes/high-contrast.json")),
+    ("tower.png", include_bytes!("../res/tower.png")),
+    ("unknown.png", include_bytes!("../res/unknown.png")),
+    ("villa.png", include_bytes!("../res/villa.png")),
+];
+#[cfg(not(feature = "embed-assets"))]
+const EMBEDDED: &[(&str, &[u8])] = &[];
+
+/// Finds asset files like sprites, fonts and sounds. Each file is looked up in
+/// a list of resource directories, so single files can be overridden.
+#[derive(Clone, Debug)]
+pub struct Resources {
+    dirs: Vec<PathBuf>,
+}
+
+/// A resource file on disk or embedded into the executable.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Resource {
+    File(PathBuf),
+    Embedded(&'static str, &'static [u8]),
+}
+
+impl Resources {
+    /// Searches `custom` first, then the user data directory, the directory of
+    /// the executable and, in debug builds, the resources of the source tree.
+    pub fn new(custom: Option<&str>) -> Resources {
+        let mut dirs = Vec::new();
+        dirs.extend(custom.map(PathBuf::from));
+        dirs.extend(Resources::data_dir());
+        if let Some(exe) = env::current_exe().ok().and_then(|e| e.parent().map(Path::to_path_buf)) {
+            if cfg!(target_os = "macos") {
+                dirs.push(exe.join("../Resources/res"));
+            }
+            dirs.push(exe.join("res"));
+        }
+        if cfg!(debug_assertions) {
+            // Release builds must not depend on the machine they were built on.
+            dirs.push(PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/res")));
+        }
+        debug!("Resource directories: {:?}", dirs);
+        Resources { dirs }
+    }
+
+    /// User specific resources, e.g. `~/.local/share/epoch-wars/`.
+    fn data_dir() -> Option<PathBuf> {
+        let base = if cfg!(target_os = "windows") {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_DATA_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))
+        };
+        base.map(|b| b.join("epoch-wars"))
+    }
+
+    /// Returns the path of the directory `name` in the first resource directory
+    /// containing it.
+    pub fn dir(&self, name: &str) -> Option<PathBuf> {
+        self.dirs.iter().map(|d| d.join(name)).find(|p| p.is_dir())
+    }
+
+    /// Returns the file `name` from the first resource directory containing it,
+    /// or the embedded resource.
+    pub fn find(&self, name: &str) -> Option<Resource> {
+        self.dirs
+            .iter()
+            .map(|d| d.join(name))
+            .find(|p| p.is_file())
+            .map(Resource::File)
+            .or_else(|| {
+                EMBEDDED
+                    .iter()
+                    .find(|e| e.0 == name)
+                    .map(|e| Resource::Embedded(e.0, e.1))
+            })
+    }
+
+    /// Like `find`, but reports where the missing resource was searched.
+    pub fn get(&self, name: &str) -> Result<Resource, Error> {
+        self.find(name).ok_or_else(|| {
+            let dirs = self
+                .dirs
+                .iter()
+                .map(|d| d.display().to_string())
+                .collect::<Vec<_>>();
+            format_err!("Missing resource {}, searched in {}.", name, dirs.join(", "))
+        })
+    }
+}
+
+impl Resource {
+    /// Reads the whole resource.
+    pub fn data(&self) -> Result<Cow<'static, [u8]>, Error> {
+        match self {
+            Resource::File(path) => Ok(Cow::Owned(
+                fs::read(path).map_err(|e| format_err!("Unable to read {}: {}", self, e))?,
+            )),
+            Resource::Embedded(_, data) => Ok(Cow::Borrowed(data)),
+        }
+    }
+
+    /// Opens the resource for loading images and fonts with SDL.
+    pub fn rwops(&self) -> Result<RWops<'static>, Error> {
+        match self {
+            Resource::File(path) => RWops::from_file(path, "rb"),
+            Resource::Embedded(_, data) => RWops::from_bytes(data),
+        }.map_err(|e| format_err!("Unable to open {}: {}", self, e))
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resource::File(path) => write!(f, "{}", path.display()),
+            Resource::Embedded(name, _) => write!(f, "embedded {}", name),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "embed-assets"))]
+mod tests {
+    use super::*;
+    use super::super::theme::Theme;
+
+    #[test]
+    fn defaults_are_embedded() {
+        let resources = Resources { dirs: Vec::new() };
+        let mut names = vec![
+            "bg.ogg".to_string(),
+            "build.ogg".to_string(),
+            "locales/de.ftl".to_string(),
+            "locales/en.ftl".to_string(),
+        ];
+        let themes = vec![
+            Theme::default(),
+            Theme::load(&resources, "default").unwrap(),
+            Theme::load(&resources, "high-contrast").unwrap(),
+        ];
+        for theme in themes {
+            let s = theme.sprites;
+            names.extend(vec![
+                theme.font,
+                s.house,
+                s.villa,
+                s.tower,
+                s.unknown,
+                s.background,
+                s.excavation,
+                s.skip,
+            ]);
+        }
+        for name in names {
+            match resources.find(&name) {
+                Some(Resource::Embedded(..)) => {}
+                r => panic!("{} resolved to {:?}", name, r),
+            }
+        }
+    }
+}
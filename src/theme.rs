@@ -0,0 +1,240 @@
+use failure::Error;
+use sdl2::pixels::Color;
+use serde::de::{self, Deserialize, Deserializer};
+
+use super::resources::Resources;
+
+/// Look of the GUI, loaded from `themes/<name>.json` in the resource
+/// directories. Missing entries keep their default.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub sprites: Sprites,
+    /// Resource name of the font.
+    pub font: String,
+    pub colors: Colors,
+    pub sizes: Sizes,
+}
+
+/// Resource names of the images.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Sprites {
+    pub house: String,
+    pub villa: String,
+    pub tower: String,
+    pub unknown: String,
+    pub background: String,
+    pub excavation: String,
+    pub skip: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct Colors {
+    #[serde(deserialize_with = "color")]
+    pub background: Color,
+    #[serde(deserialize_with = "color")]
+    pub text: Color,
+    /// Outline of the cell or action under the mouse.
+    #[serde(deserialize_with = "color")]
+    pub hover: Color,
+    /// Outline of the selected building.
+    #[serde(deserialize_with = "color")]
+    pub selected: Color,
+    /// Checkmark on actions already used this turn.
+    #[serde(deserialize_with = "color")]
+    pub done: Color,
+    #[serde(deserialize_with = "color")]
+    pub box_background: Color,
+    #[serde(deserialize_with = "color")]
+    pub box_border: Color,
+    #[serde(deserialize_with = "color")]
+    pub connection_good: Color,
+    #[serde(deserialize_with = "color")]
+    pub connection_fair: Color,
+    #[serde(deserialize_with = "color")]
+    pub connection_poor: Color,
+    #[serde(deserialize_with = "color")]
+    pub connection_unknown: Color,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Sizes {
+    /// Point size the font is rendered with before scaling.
+    pub font: u16,
+    /// Lines of text fitting the window height.
+    pub text_lines: u32,
+    pub min_line_height: i32,
+    /// Width of outlines in pixels.
+    pub outline: u32,
+    /// Opacity of planned buildings and used actions.
+    pub planned_alpha: u8,
+}
+
+/// Colours replacing those of the theme for hover, selection and validity,
+/// for players who cannot tell them apart.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Palette {
+    #[default]
+    Theme,
+    /// Blue and orange instead of green and red, for protanopia and
+    /// deuteranopia.
+    RedGreen,
+    /// Teal and red instead of blue and yellow, for tritanopia.
+    BlueYellow,
+    Monochrome,
+}
+
+pub const PALETTES: [Palette; 4] = [
+    Palette::Theme,
+    Palette::RedGreen,
+    Palette::BlueYellow,
+    Palette::Monochrome,
+];
+
+impl Theme {
+    pub fn load(resources: &Resources, name: &str) -> Result<Theme, Error> {
+        let resource = resources.get(&format!("themes/{}.json", name))?;
+        let theme = serde_json::from_slice(&resource.data()?)
+            .map_err(|e| format_err!("Invalid theme {}: {}", resource, e))?;
+        debug!("Loaded theme {}", resource);
+        Ok(theme)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            sprites: Sprites::default(),
+            font: "font.ttf".to_string(),
+            colors: Colors::default(),
+            sizes: Sizes::default(),
+        }
+    }
+}
+
+impl Default for Sprites {
+    fn default() -> Sprites {
+        Sprites {
+            house: "house.png".to_string(),
+            villa: "villa.png".to_string(),
+            tower: "tower.png".to_string(),
+            unknown: "unknown.png".to_string(),
+            background: "bg.png".to_string(),
+            excavation: "ex.png".to_string(),
+            skip: "skip.png".to_string(),
+        }
+    }
+}
+
+impl Default for Colors {
+    fn default() -> Colors {
+        Colors {
+            background: Color::RGB(50, 50, 50),
+            text: Color::RGB(255, 255, 255),
+            hover: Color::RGB(255, 0, 0),
+            selected: Color::RGB(0, 200, 0),
+            done: Color::RGB(0, 200, 0),
+            box_background: Color::RGBA(20, 20, 20, 220),
+            box_border: Color::RGB(200, 200, 200),
+            connection_good: Color::RGB(0, 200, 0),
+            connection_fair: Color::RGB(230, 200, 0),
+            connection_poor: Color::RGB(220, 0, 0),
+            connection_unknown: Color::RGB(150, 150, 150),
+        }
+    }
+}
+
+impl Colors {
+    /// Returns these colours with hover, selection and validity colours
+    /// replaced by those of `palette`.
+    pub fn with_palette(&self, palette: Palette) -> Colors {
+        let (hover, selected, done, good, fair, poor) = match palette {
+            Palette::Theme => return *self,
+            Palette::RedGreen => (
+                Color::RGB(230, 159, 0),
+                Color::RGB(0, 114, 178),
+                Color::RGB(86, 180, 233),
+                Color::RGB(0, 114, 178),
+                Color::RGB(240, 228, 66),
+                Color::RGB(213, 94, 0),
+            ),
+            Palette::BlueYellow => (
+                Color::RGB(230, 0, 0),
+                Color::RGB(0, 160, 160),
+                Color::RGB(0, 160, 160),
+                Color::RGB(0, 160, 160),
+                Color::RGB(204, 121, 167),
+                Color::RGB(230, 0, 0),
+            ),
+            Palette::Monochrome => (
+                Color::RGB(255, 255, 255),
+                Color::RGB(180, 180, 180),
+                Color::RGB(255, 255, 255),
+                Color::RGB(255, 255, 255),
+                Color::RGB(180, 180, 180),
+                Color::RGB(110, 110, 110),
+            ),
+        };
+        Colors {
+            hover,
+            selected,
+            done,
+            connection_good: good,
+            connection_fair: fair,
+            connection_poor: poor,
+            ..*self
+        }
+    }
+}
+
+impl Palette {
+    pub fn name(self) -> String {
+        tr!(match self {
+            Palette::Theme => "palette-theme",
+            Palette::RedGreen => "palette-red-green",
+            Palette::BlueYellow => "palette-blue-yellow",
+            Palette::Monochrome => "palette-monochrome",
+        })
+    }
+}
+
+impl Default for Sizes {
+    fn default() -> Sizes {
+        Sizes {
+            font: 120,
+            text_lines: 30,
+            min_line_height: 12,
+            outline: 1,
+            planned_alpha: 100,
+        }
+    }
+}
+
+impl Sizes {
+    /// Height of a line of text in a window of height `h`.
+    pub fn line_height(&self, h: u32) -> i32 {
+        ((h / self.text_lines.max(1)) as i32).max(self.min_line_height)
+    }
+}
+
+/// Parses a colour written as `"#rrggbb"` or `"#rrggbbaa"`.
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_color(&s).ok_or_else(|| {
+        de::Error::custom(format!("invalid colour {}, expected #rrggbb or #rrggbbaa", s))
+    })
+}
+
+fn parse_color(s: &str) -> Option<Color> {
+    let hex = s.strip_prefix('#')?;
+    if !hex.is_ascii() || (hex.len() != 6 && hex.len() != 8) {
+        return None;
+    }
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    let alpha = if hex.len() == 8 { byte(6)? } else { 255 };
+    Some(Color::RGBA(byte(0)?, byte(2)?, byte(4)?, alpha))
+}
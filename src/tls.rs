@@ -0,0 +1,268 @@
+use failure::Error;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    pub enabled: bool,
+    pub ca_file: Option<String>,
+    pub pin: Option<String>,
+}
+
+#[derive(Debug)]
+struct PinnedVerifier {
+    fingerprint: Vec<u8>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl TlsOptions {
+    fn config(&self) -> Result<ClientConfig, Error> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let config = if let Some(ref pin) = self.pin {
+            let verifier = PinnedVerifier {
+                fingerprint: parse_fingerprint(pin)?,
+                provider,
+            };
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+                .with_no_client_auth()
+        } else {
+            let mut roots = RootCertStore::empty();
+            if let Some(ref path) = self.ca_file {
+                let mut reader = BufReader::new(File::open(path)?);
+                for cert in rustls_pemfile::certs(&mut reader) {
+                    roots.add(cert?)?;
+                }
+                if roots.is_empty() {
+                    return Err(format_err!("No certificates found in {}.", path));
+                }
+            } else {
+                roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            }
+            builder
+                .with_root_certificates(roots)
+                .with_no_client_auth()
+        };
+        Ok(config)
+    }
+
+    pub async fn connect(
+        &self,
+        address: &str,
+        sock: TcpStream,
+    ) -> Result<TlsStream<TcpStream>, Error> {
+        let host = address
+            .rsplitn(2, ':')
+            .last()
+            .unwrap_or(address)
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|_| format_err!("Invalid server name {}.", host))?;
+        let connector = TlsConnector::from(Arc::new(self.config()?));
+        let stream = connector.connect(name, sock).await?;
+        debug!("TLS handshake with {} finished.", host);
+        Ok(stream)
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _server_name: &ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Sha256::digest(end_entity.as_ref()).as_slice() == &self.fingerprint[..] {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "Server certificate does not match the pinned fingerprint.".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn parse_fingerprint(pin: &str) -> Result<Vec<u8>, Error> {
+    let hex = pin
+        .chars()
+        .filter(|c| *c != ':')
+        .collect::<String>();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(format_err!("Certificate pin must be a SHA-256 fingerprint."));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format_err!("Invalid certificate pin {}.", pin))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{generate_simple_self_signed, CertifiedKey};
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::ServerConfig;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    const FINGERPRINT: &str = concat!(
+        "00:11:22:33:44:55:66:77:88:99:aa:bb:cc:dd:ee:ff:",
+        "00:11:22:33:44:55:66:77:88:99:AA:BB:CC:DD:EE:FF"
+    );
+
+    fn certificate() -> CertifiedKey {
+        generate_simple_self_signed(vec!["localhost".to_string()]).unwrap()
+    }
+
+    fn fingerprint(cert: &CertificateDer) -> String {
+        Sha256::digest(cert.as_ref())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn verifier(pin: &str) -> PinnedVerifier {
+        PinnedVerifier {
+            fingerprint: parse_fingerprint(pin).unwrap(),
+            provider: Arc::new(ring::default_provider()),
+        }
+    }
+
+    fn verify(verifier: &PinnedVerifier, cert: &CertificateDer) -> bool {
+        let name = ServerName::try_from("localhost").unwrap();
+        verifier
+            .verify_server_cert(cert, &[], &name, &[], UnixTime::now())
+            .is_ok()
+    }
+
+    /// Serves a single TLS connection with `key` on a loopback port.
+    async fn server(key: CertifiedKey) -> u16 {
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![key.cert.der().clone()],
+                PrivateKeyDer::Pkcs8(key.key_pair.serialize_der().into()),
+            )
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (sock, _) = listener.accept().await.unwrap();
+            if let Ok(mut stream) = TlsAcceptor::from(Arc::new(config)).accept(sock).await {
+                let _ = stream.write_all(b"hello\n").await;
+            }
+        });
+        port
+    }
+
+    async fn connect(port: u16, pin: &str) -> Result<TlsStream<TcpStream>, Error> {
+        let options = TlsOptions {
+            enabled: true,
+            ca_file: None,
+            pin: Some(pin.to_string()),
+        };
+        let sock = TcpStream::connect(("127.0.0.1", port)).await?;
+        options.connect(&format!("localhost:{}", port), sock).await
+    }
+
+    #[test]
+    fn parse_fingerprint_with_colons() {
+        let fingerprint = parse_fingerprint(FINGERPRINT).unwrap();
+        assert_eq!(fingerprint.len(), 32);
+        assert_eq!(&fingerprint[..3], &[0x00, 0x11, 0x22]);
+        assert_eq!(fingerprint[31], 0xff);
+        assert_eq!(parse_fingerprint(&FINGERPRINT.replace(':', "")).unwrap(), fingerprint);
+    }
+
+    #[test]
+    fn parse_malformed_fingerprint() {
+        assert!(parse_fingerprint("").is_err());
+        assert!(parse_fingerprint("00:11:22").is_err());
+        assert!(parse_fingerprint(&FINGERPRINT.replace("ff", "fg")).is_err());
+        assert!(parse_fingerprint(&FINGERPRINT.replace("ff", "ä")).is_err());
+    }
+
+    #[test]
+    fn pinned_verifier_accepts_matching_certificate() {
+        let key = certificate();
+        assert!(verify(&verifier(&fingerprint(key.cert.der())), key.cert.der()));
+    }
+
+    #[test]
+    fn pinned_verifier_rejects_other_certificate() {
+        let key = certificate();
+        assert!(!verify(&verifier(FINGERPRINT), key.cert.der()));
+        let other = certificate();
+        assert!(!verify(&verifier(&fingerprint(other.cert.der())), key.cert.der()));
+    }
+
+    #[tokio::test]
+    async fn handshake_with_pinned_server() {
+        let key = certificate();
+        let pin = fingerprint(key.cert.der());
+        let port = server(key).await;
+        assert!(connect(port, &pin).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn handshake_refuses_pin_mismatch() {
+        let port = server(certificate()).await;
+        assert!(connect(port, FINGERPRINT).await.is_err());
+    }
+}
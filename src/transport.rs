@@ -0,0 +1,534 @@
+use failure::{self, Error, Fail};
+use futures_util::future::BoxFuture;
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use socket2::{SockRef, TcpKeepalive};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{self, TcpStream};
+#[cfg(test)]
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time;
+use tokio_rustls::client::TlsStream;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{self, WebSocketStream};
+use tokio_util::codec::{Framed, LinesCodec, LinesCodecError};
+
+use super::proxy::Proxy;
+use super::tls::TlsOptions;
+
+/// Idle time before the operating system starts sending TCP keepalive probes.
+const KEEPALIVE: u64 = 15;
+/// Seconds until sending or closing a connection is given up.
+const SEND_TIMEOUT: u64 = 5;
+/// Longest message accepted from the server in bytes, so a server that never
+/// ends a message cannot make the client buffer without limit.
+const MAX_MESSAGE: usize = 1 << 20;
+
+/// A connection carrying one JSON message per `send`/`receive`.
+pub trait Transport: Send {
+    fn send<'a>(&'a mut self, message: &'a str) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// Returns `None` once the connection was closed by the server. No message
+    /// may be lost when the returned future is dropped, so it can be raced
+    /// against other events.
+    fn receive(&mut self) -> BoxFuture<'_, Result<Option<String>, Error>>;
+
+    fn close(&mut self) -> BoxFuture<'_, Result<(), Error>>;
+}
+
+/// Newline separated messages over a plain TCP or TLS stream.
+pub struct LineTransport {
+    lines: Framed<Stream, LinesCodec>,
+}
+
+pub struct WebSocketTransport {
+    socket: WebSocketStream<Stream>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectOptions {
+    pub tls: TlsOptions,
+    pub proxy: Option<Proxy>,
+    /// Timeout for a single connection attempt.
+    pub timeout: Duration,
+    /// Delay before racing the next resolved address.
+    pub attempt_delay: Duration,
+}
+
+/// Context of a failure to resolve a host name.
+#[derive(Debug)]
+struct Unresolved(String);
+
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scheme {
+    Tcp,
+    Ws,
+    Wss,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> ConnectOptions {
+        ConnectOptions {
+            tls: TlsOptions::default(),
+            proxy: None,
+            timeout: Duration::from_millis(20000),
+            attempt_delay: Duration::from_millis(250),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(s) => Pin::new(s).poll_read(cx, buf),
+            Stream::Tls(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(s) => Pin::new(s).poll_write(cx, buf),
+            Stream::Tls(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(s) => Pin::new(s).poll_flush(cx),
+            Stream::Tls(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(s) => Pin::new(s).poll_shutdown(cx),
+            Stream::Tls(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+impl LineTransport {
+    fn new(stream: Stream) -> LineTransport {
+        LineTransport {
+            lines: Framed::new(stream, LinesCodec::new_with_max_length(MAX_MESSAGE)),
+        }
+    }
+}
+
+impl Transport for LineTransport {
+    fn send<'a>(&'a mut self, message: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        let sent = async move { Ok(self.lines.send(message).await?) };
+        with_timeout(sent, "Sending to the server timed out.").boxed()
+    }
+
+    fn receive(&mut self) -> BoxFuture<'_, Result<Option<String>, Error>> {
+        async move {
+            match self.lines.next().await {
+                Some(Ok(line)) => Ok(Some(line.trim().to_string())),
+                Some(Err(LinesCodecError::MaxLineLengthExceeded)) => Err(too_long()),
+                Some(Err(LinesCodecError::Io(e))) => Err(e.into()),
+                None => Ok(None),
+            }
+        }.boxed()
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, Result<(), Error>> {
+        let closed = async move { Ok(self.lines.get_mut().shutdown().await?) };
+        with_timeout(closed, "Closing the connection timed out.").boxed()
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn send<'a>(&'a mut self, message: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        let sent = async move { Ok(self.socket.send(Message::text(message)).await?) };
+        with_timeout(sent, "Sending to the server timed out.").boxed()
+    }
+
+    fn receive(&mut self) -> BoxFuture<'_, Result<Option<String>, Error>> {
+        async move {
+            loop {
+                match self.socket.next().await {
+                    Some(Ok(Message::Text(text))) => return Ok(Some(text.trim().to_string())),
+                    Some(Ok(Message::Close(_))) | None => return Ok(None),
+                    Some(Ok(msg)) => trace!("Ignoring WebSocket message {:?}", msg),
+                    Some(Err(tungstenite::Error::ConnectionClosed))
+                    | Some(Err(tungstenite::Error::AlreadyClosed)) => return Ok(None),
+                    Some(Err(tungstenite::Error::Capacity(_))) => return Err(too_long()),
+                    Some(Err(e)) => return Err(e.into()),
+                }
+            }
+        }.boxed()
+    }
+
+    /// Sends a close frame.
+    fn close(&mut self) -> BoxFuture<'_, Result<(), Error>> {
+        let closed = async move { Ok(self.socket.close(None).await?) };
+        with_timeout(closed, "Closing the connection timed out.").boxed()
+    }
+}
+
+/// Messages passed through channels, for testing without a server.
+#[cfg(test)]
+pub struct MemoryTransport {
+    tx: mpsc::UnboundedSender<String>,
+    rx: mpsc::UnboundedReceiver<String>,
+}
+
+#[cfg(test)]
+impl MemoryTransport {
+    /// Returns both ends of a connection.
+    pub fn pair() -> (MemoryTransport, MemoryTransport) {
+        let (tx_a, rx_a) = mpsc::unbounded_channel();
+        let (tx_b, rx_b) = mpsc::unbounded_channel();
+        (
+            MemoryTransport { tx: tx_a, rx: rx_b },
+            MemoryTransport { tx: tx_b, rx: rx_a },
+        )
+    }
+}
+
+#[cfg(test)]
+impl Transport for MemoryTransport {
+    fn send<'a>(&'a mut self, message: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        let sent = self
+            .tx
+            .send(message.to_string())
+            .map_err(|_| format_err!("Connection closed."));
+        async move { sent }.boxed()
+    }
+
+    fn receive(&mut self) -> BoxFuture<'_, Result<Option<String>, Error>> {
+        async move { Ok(self.rx.recv().await) }.boxed()
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, Result<(), Error>> {
+        self.rx.close();
+        async { Ok(()) }.boxed()
+    }
+}
+
+impl fmt::Display for Unresolved {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unable to resolve {}.", self.0)
+    }
+}
+
+/// Returns the id of the catalog message describing why a connection failed.
+pub fn error_id(err: &Error) -> &'static str {
+    for cause in err.iter_chain() {
+        if cause.downcast_ref::<failure::Context<Unresolved>>().is_some() {
+            return "error-resolve";
+        }
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            return io_error_id(e);
+        }
+        if cause.downcast_ref::<rustls::Error>().is_some() {
+            return "error-tls";
+        }
+        match cause.downcast_ref::<tungstenite::Error>() {
+            Some(tungstenite::Error::Io(e)) => return io_error_id(e),
+            Some(tungstenite::Error::Tls(_)) => return "error-tls",
+            Some(tungstenite::Error::ConnectionClosed)
+            | Some(tungstenite::Error::AlreadyClosed) => return "error-closed",
+            _ => {}
+        }
+    }
+    "error-connection"
+}
+
+fn io_error_id(err: &io::Error) -> &'static str {
+    // TLS errors are reported as I/O errors by the TLS stream.
+    if err.get_ref().is_some_and(|e| e.is::<rustls::Error>()) {
+        return "error-tls";
+    }
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused => "error-refused",
+        io::ErrorKind::TimedOut => "error-timeout",
+        io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::UnexpectedEof => "error-closed",
+        _ => "error-connection",
+    }
+}
+
+fn too_long() -> Error {
+    format_err!("The server sent a message longer than {} bytes.", MAX_MESSAGE)
+}
+
+/// Gives up on `future` after the send timeout.
+async fn with_timeout<F>(future: F, message: &'static str) -> Result<(), Error>
+where
+    F: std::future::Future<Output = Result<(), Error>>,
+{
+    time::timeout(Duration::from_secs(SEND_TIMEOUT), future)
+        .await
+        .map_err(|_| format_err!("{}", message))?
+}
+
+impl Scheme {
+    fn parse(address: &str) -> (Scheme, &str) {
+        if let Some(rest) = address.strip_prefix("ws://") {
+            (Scheme::Ws, rest)
+        } else if let Some(rest) = address.strip_prefix("wss://") {
+            (Scheme::Wss, rest)
+        } else if let Some(rest) = address.strip_prefix("tcp://") {
+            (Scheme::Tcp, rest)
+        } else {
+            (Scheme::Tcp, address)
+        }
+    }
+}
+
+/// Resolves the game server address announced by the session server. Addresses
+/// without a scheme use the scheme of the session server.
+pub fn resolve(announced: &str, session: &str) -> String {
+    if announced.contains("://") {
+        return announced.to_string();
+    }
+    match Scheme::parse(session).0 {
+        Scheme::Tcp => announced.to_string(),
+        Scheme::Ws => format!("ws://{}", announced),
+        Scheme::Wss => format!("wss://{}", announced),
+    }
+}
+
+/// Connects to any of the addresses `host` resolves to. Addresses of both
+/// families are tried alternately, starting a new attempt whenever the previous
+/// one failed or did not succeed within the attempt delay (happy eyeballs).
+async fn connect_tcp(
+    host: &str,
+    options: &ConnectOptions,
+    report: &(dyn Fn(String) + Sync),
+) -> Result<TcpStream, Error> {
+    let resolved = net::lookup_host(host)
+        .await
+        .map_err(|e| e.context(Unresolved(host.to_string())))?
+        .collect::<Vec<_>>();
+    let prefer_v6 = resolved.first().is_none_or(|a| a.is_ipv6());
+    let (v6, v4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        resolved.into_iter().partition(|a| a.is_ipv6());
+    let (first, second) = if prefer_v6 { (v6, v4) } else { (v4, v6) };
+    let mut addresses: Vec<SocketAddr> = Vec::new();
+    for i in 0..first.len().max(second.len()) {
+        addresses.extend(first.get(i));
+        addresses.extend(second.get(i));
+    }
+    if addresses.is_empty() {
+        return Err(failure::Context::new(Unresolved(host.to_string())).into());
+    }
+    race(host, addresses, options, report).await
+}
+
+/// Connects to the first of `addresses` accepting a connection, starting the
+/// next attempt after the attempt delay or as soon as one fails.
+async fn race(
+    host: &str,
+    addresses: Vec<SocketAddr>,
+    options: &ConnectOptions,
+    report: &(dyn Fn(String) + Sync),
+) -> Result<TcpStream, Error> {
+    let mut attempts = JoinSet::<(SocketAddr, io::Result<TcpStream>)>::new();
+    let mut addresses = addresses.into_iter();
+    let mut errors = Vec::new();
+    let mut last = None;
+    loop {
+        let wait = if attempts.is_empty() {
+            Duration::from_millis(0)
+        } else {
+            options.attempt_delay
+        };
+        match time::timeout(wait, attempts.join_next()).await {
+            Ok(Some(Ok((address, Ok(stream))))) => {
+                report(tr!("connected", address = address.to_string()));
+                // Slower attempts would open connections that are never used.
+                attempts.abort_all();
+                return Ok(stream);
+            }
+            Ok(Some(Ok((address, Err(e))))) => {
+                report(tr!(
+                    "connection-failed",
+                    address = address.to_string(),
+                    error = tr!(io_error_id(&e))
+                ));
+                errors.push(format!("{}: {}", address, e));
+                last = Some(e);
+            }
+            Ok(Some(Err(e))) => warn!("Connection attempt failed: {}", e),
+            Ok(None) | Err(_) => {}
+        }
+        match addresses.next() {
+            Some(address) => {
+                report(tr!("connecting", address = address.to_string()));
+                let timeout = options.timeout;
+                attempts.spawn(async move {
+                    let result = match time::timeout(timeout, TcpStream::connect(address)).await {
+                        Ok(result) => result,
+                        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+                    };
+                    (address, result)
+                });
+            }
+            None if attempts.is_empty() => break,
+            None => {}
+        }
+    }
+    // The last error is kept as the cause, so the reason can be shown.
+    let message = format!("Unable to connect to {} ({}).", host, errors.join(", "));
+    Err(match last {
+        Some(e) => e.context(message).into(),
+        None => format_err!("{}", message),
+    })
+}
+
+async fn open(
+    host: &str,
+    tls: bool,
+    options: &ConnectOptions,
+    report: &(dyn Fn(String) + Sync),
+) -> Result<Stream, Error> {
+    let stream = match options.proxy {
+        Some(ref proxy) => {
+            let mut stream = connect_tcp(proxy.address(), options, report).await?;
+            proxy.tunnel(&mut stream, host, options.timeout).await?;
+            stream
+        }
+        None => connect_tcp(host, options, report).await?,
+    };
+    let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(KEEPALIVE));
+    if let Err(e) = SockRef::from(&stream).set_tcp_keepalive(&keepalive) {
+        warn!("Unable to enable TCP keepalive: {}", e);
+    }
+    if tls {
+        Ok(Stream::Tls(Box::new(options.tls.connect(host, stream).await?)))
+    } else {
+        Ok(Stream::Plain(stream))
+    }
+}
+
+/// Connects to `address`, choosing the transport by its scheme (`tcp://`,
+/// `ws://` or `wss://`, plain TCP if none is given).
+pub async fn connect(
+    address: &str,
+    options: &ConnectOptions,
+    report: &(dyn Fn(String) + Sync),
+) -> Result<Box<dyn Transport>, Error> {
+    let (scheme, rest) = Scheme::parse(address);
+    match scheme {
+        Scheme::Tcp => {
+            let stream = open(rest, options.tls.enabled, options, report).await?;
+            Ok(Box::new(LineTransport::new(stream)))
+        }
+        Scheme::Ws | Scheme::Wss => {
+            let authority = rest.split('/').next().unwrap_or(rest);
+            let host = if authority.contains(':') && !authority.ends_with(']') {
+                authority.to_string()
+            } else if scheme == Scheme::Wss {
+                format!("{}:443", authority)
+            } else {
+                format!("{}:80", authority)
+            };
+            let tls = scheme == Scheme::Wss || options.tls.enabled;
+            let stream = open(&host, tls, options, report).await?;
+            let config = WebSocketConfig::default()
+                .max_message_size(Some(MAX_MESSAGE))
+                .max_frame_size(Some(MAX_MESSAGE));
+            let (socket, _) =
+                tokio_tungstenite::client_async_with_config(address, stream, Some(config))
+                    .await
+                    .map_err(|e| format_err!("WebSocket handshake failed: {}", e))?;
+            Ok(Box::new(WebSocketTransport { socket }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn race_starts_next_attempt_after_failure() {
+        let refused = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listening = listener.local_addr().unwrap();
+        let options = ConnectOptions {
+            attempt_delay: Duration::from_secs(10),
+            ..ConnectOptions::default()
+        };
+        let start = Instant::now();
+        let stream = race("localhost", vec![refused, listening], &options, &|_| {})
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listening);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn race_reports_all_failures() {
+        let refused = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let options = ConnectOptions::default();
+        let err = race("localhost", vec![refused, refused], &options, &|_| {})
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Unable to connect to localhost"));
+        assert_eq!(error_id(&err), "error-refused");
+    }
+
+    #[test]
+    fn error_ids() {
+        let unresolved = failure::Context::new(Unresolved("game".to_string())).into();
+        assert_eq!(error_id(&unresolved), "error-resolve");
+        let timeout = io::Error::from(io::ErrorKind::TimedOut);
+        assert_eq!(error_id(&timeout.into()), "error-timeout");
+        let closed = io::Error::from(io::ErrorKind::UnexpectedEof);
+        assert_eq!(error_id(&closed.context("Handshake failed.").into()), "error-closed");
+        let tls = io::Error::new(io::ErrorKind::InvalidData, rustls::Error::DecryptError);
+        assert_eq!(error_id(&tls.into()), "error-tls");
+        assert_eq!(error_id(&tungstenite::Error::ConnectionClosed.into()), "error-closed");
+        assert_eq!(error_id(&format_err!("Connection lost.")), "error-connection");
+    }
+
+    #[test]
+    fn resolve_uses_scheme_of_session_server() {
+        assert_eq!(resolve("game:4201", "ws://session:4200"), "ws://game:4201");
+        assert_eq!(resolve("game:4201", "wss://session"), "wss://game:4201");
+        assert_eq!(resolve("game:4201", "session:4200"), "game:4201");
+        assert_eq!(resolve("tcp://game:4201", "wss://session"), "tcp://game:4201");
+    }
+
+    #[tokio::test]
+    async fn line_transport_rejects_long_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut sock, _) = listener.accept().await.unwrap();
+            let _ = sock.write_all(b"{}\n").await;
+            let _ = sock.write_all(&vec![b'x'; MAX_MESSAGE + 1]).await;
+        });
+        let stream = Stream::Plain(TcpStream::connect(address).await.unwrap());
+        let mut transport = LineTransport::new(stream);
+        assert_eq!(transport.receive().await.unwrap(), Some("{}".to_string()));
+        let err = transport.receive().await.unwrap_err();
+        assert!(err.to_string().contains("longer than"));
+    }
+}